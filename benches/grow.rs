@@ -0,0 +1,43 @@
+use {
+    criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion},
+    id_storage::CacheStorage,
+};
+
+const BURST_SIZE: usize = 100_000;
+
+fn bench_burst_insert(c: &mut Criterion) {
+    c.bench_function("naive inserts", |b| {
+        b.iter_batched(
+            CacheStorage::new,
+            |mut storage| {
+                for i in 0..BURST_SIZE {
+                    storage.insert(black_box(i));
+                }
+
+                storage
+            },
+            BatchSize::LargeInput
+        )
+    });
+
+    c.bench_function("pre-grown inserts", |b| {
+        b.iter_batched(
+            || {
+                let mut storage = CacheStorage::new();
+                storage.grow(BURST_SIZE);
+                storage
+            },
+            |mut storage| {
+                for i in 0..BURST_SIZE {
+                    storage.insert(black_box(i));
+                }
+
+                storage
+            },
+            BatchSize::LargeInput
+        )
+    });
+}
+
+criterion_group!(benches, bench_burst_insert);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+use {
+    criterion::{black_box, criterion_group, criterion_main, Criterion},
+    id_storage::{CacheStorage, Growth},
+    std::time::{Duration, Instant},
+};
+
+/// Long enough for a reallocation to copy tens of megabytes.
+const BURST_SIZE: usize = 1_000_000;
+
+/// Measures the slowest single insert of a burst of large elements.
+fn worst_insert(growth: Growth) -> Duration {
+    let mut storage = CacheStorage::new();
+    storage.set_growth(growth);
+
+    let mut worst = Duration::default();
+    for i in 0..BURST_SIZE {
+        let start = Instant::now();
+        storage.insert(black_box([i; 16]));
+        worst = worst.max(start.elapsed());
+    }
+
+    worst
+}
+
+fn bench_growth_tail_latency(c: &mut Criterion) {
+    let mut group = c.benchmark_group("worst insert");
+    group.sample_size(10);
+
+    for &(name, growth) in [
+        ("doubling growth", Growth::Doubling),
+        ("fixed growth", Growth::Fixed(1024)),
+    ].iter() {
+        group.bench_function(name, |b| {
+            b.iter_custom(|iters| (0..iters).map(|_| worst_insert(growth)).sum())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_growth_tail_latency);
+criterion_main!(benches);
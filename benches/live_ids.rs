@@ -0,0 +1,42 @@
+use {
+    criterion::{black_box, criterion_group, criterion_main, Criterion},
+    id_storage::ShrinkableStorage,
+};
+
+const ELEMENTS_NUM: usize = 100_000;
+
+/// Every 3rd element is freed.
+fn fragmented_storage() -> ShrinkableStorage<usize> {
+    let mut storage = ShrinkableStorage::new();
+    storage.extend(0..ELEMENTS_NUM);
+
+    let free_ids: Vec<_> = storage.iter_ids().filter(|&id| id % 3 == 0).collect();
+    storage.free_ids(free_ids);
+
+    storage
+}
+
+fn bench_live_ids(c: &mut Criterion) {
+    let storage = fragmented_storage();
+
+    c.bench_function("live ids filter by contains", |b| {
+        b.iter(|| {
+            storage.iter_ids()
+                .filter(|id| !storage.is_id_free(id))
+                .for_each(|id| {
+                    black_box(id);
+                })
+        })
+    });
+
+    c.bench_function("live ids merge walk", |b| {
+        b.iter(|| {
+            storage.live_ids().for_each(|id| {
+                black_box(id);
+            })
+        })
+    });
+}
+
+criterion_group!(benches, bench_live_ids);
+criterion_main!(benches);
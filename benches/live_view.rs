@@ -0,0 +1,42 @@
+use {
+    criterion::{black_box, criterion_group, criterion_main, Criterion},
+    id_storage::{CacheStorage, Id},
+};
+
+const ELEMENTS_NUM: Id = 10_000;
+
+fn fragmented_storage() -> CacheStorage<Id> {
+    let mut storage = CacheStorage::new();
+    storage.extend(0..ELEMENTS_NUM);
+
+    for id in (0..ELEMENTS_NUM).step_by(3) {
+        storage.remove(id);
+    }
+
+    storage
+}
+
+fn bench_live_checks(c: &mut Criterion) {
+    let storage = fragmented_storage();
+
+    c.bench_function("is_live per call", |b| {
+        b.iter(|| {
+            (0..ELEMENTS_NUM)
+                .filter(|&id| storage.is_live(black_box(id)))
+                .count()
+        })
+    });
+
+    c.bench_function("live_view", |b| {
+        b.iter(|| {
+            let view = storage.live_view();
+
+            (0..ELEMENTS_NUM)
+                .filter(|&id| view.contains(black_box(id)))
+                .count()
+        })
+    });
+}
+
+criterion_group!(benches, bench_live_checks);
+criterion_main!(benches);
@@ -0,0 +1,53 @@
+use {
+    criterion::{black_box, criterion_group, criterion_main, Criterion},
+    std::collections::HashSet,
+};
+
+/// The sets are built over plain `usize` ids to compare
+/// the structures themselves, regardless of the `small-ids` feature.
+const ELEMENTS_NUM: usize = 100_000;
+
+fn free_ids() -> Vec<usize> {
+    (0..ELEMENTS_NUM).step_by(3).rev().collect()
+}
+
+fn bench_liveness_sets(c: &mut Criterion) {
+    let free_ids = free_ids();
+
+    c.bench_function("liveness HashSet", |b| {
+        b.iter(|| {
+            let set: HashSet<usize> = free_ids.iter().copied().collect();
+
+            (0..ELEMENTS_NUM)
+                .filter(|id| !set.contains(black_box(id)))
+                .count()
+        })
+    });
+
+    c.bench_function("liveness sorted Vec", |b| {
+        b.iter(|| {
+            let mut set = free_ids.clone();
+            set.sort_unstable();
+
+            (0..ELEMENTS_NUM)
+                .filter(|id| set.binary_search(black_box(id)).is_err())
+                .count()
+        })
+    });
+
+    c.bench_function("liveness bitmap", |b| {
+        b.iter(|| {
+            let mut set = vec![false; ELEMENTS_NUM];
+            for &id in free_ids.iter() {
+                set[id] = true;
+            }
+
+            (0..ELEMENTS_NUM)
+                .filter(|&id| !set[black_box(id) as usize])
+                .count()
+        })
+    });
+}
+
+criterion_group!(benches, bench_liveness_sets);
+criterion_main!(benches);
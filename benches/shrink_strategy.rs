@@ -0,0 +1,44 @@
+use {
+    criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion},
+    id_storage::{ShrinkableStorage, ShrinkStrategy},
+};
+
+const ELEMENTS_NUM: usize = 100_000;
+
+/// Every 10th element is freed, as are the last 20% of them.
+/// The elements are large, so the cost of moving them dominates.
+fn clustered_storage(shrink_strategy: ShrinkStrategy) -> ShrinkableStorage<[usize; 16]> {
+    let mut storage = ShrinkableStorage::new();
+    storage.set_shrink_strategy(shrink_strategy);
+    storage.extend((0..ELEMENTS_NUM).map(|i| [i; 16]));
+
+    let tail_start = ELEMENTS_NUM / 5 * 4;
+    let free_ids = storage.iter_ids().filter(|&id| id % 10 == 0 || id >= tail_start as _);
+    storage.free_ids(free_ids.collect::<Vec<_>>());
+
+    storage
+}
+
+fn bench_shrink_strategy(c: &mut Criterion) {
+    for &(name, shrink_strategy) in [
+        ("shrink swap_remove", ShrinkStrategy::SwapRemove),
+        ("shrink fill from tail", ShrinkStrategy::FillFromTail),
+    ].iter() {
+        c.bench_function(name, |b| {
+            b.iter_batched(
+                || clustered_storage(shrink_strategy),
+                |mut storage| {
+                    storage.shrink_with(|old_id, new_id| {
+                        black_box((old_id, new_id));
+                    });
+
+                    storage
+                },
+                BatchSize::LargeInput
+            )
+        });
+    }
+}
+
+criterion_group!(benches, bench_shrink_strategy);
+criterion_main!(benches);
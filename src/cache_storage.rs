@@ -1,13 +1,92 @@
 use {
     super::{
-        id_cache::*, Id
+        id_cache::*, error::{CapacityError, DisjointError, InsertError}, frozen_storage::FrozenStorage, handle::{Handle, Key, OwnedKey, new_owner},
+        live_view::LiveView,
+        Id, index, to_id, enumerate_ids,
     },
-    std::iter::Extend
+    std::{
+        any::Any,
+        iter::{self, Extend},
+        borrow::Cow,
+        collections::{BTreeMap, HashMap, HashSet, TryReserveError},
+        hash::{Hash, Hasher},
+        mem,
+        ops::Range,
+    }
 };
 
+/// How `insert` grows the storage when there are no free ids.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Growth {
+    /// Amortized growth of `Vec`, the capacity is at least doubled.
+    #[default]
+    Doubling,
+
+    /// Issues the given number of free ids and reserves exactly that many slots at once.
+    /// It bounds the unused capacity, but it doesn't lower the tail latency of `insert`:
+    /// each step may still copy the whole storage, so the slowest insert
+    /// costs about the same as with `Doubling`, see `benches/growth.rs`.
+    Fixed(usize),
+}
+
+/// State of a slot yielded by `CacheStorage::iter_all`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SlotState {
+    Live,
+
+    /// The slot holds a stale value of a removed element.
+    Freed,
+}
+
+/// State of an id reported by `CacheStorage::classify`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IdState {
+    /// The id is above every id issued so far.
+    NeverIssued,
+    Live,
+
+    /// The id was issued and is free now, including the ids issued ahead of their slots.
+    Freed,
+}
+
+/// Size and capacity numbers returned by `CacheStorage::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageStats {
+    pub live: usize,
+
+    /// The number of issued ids, `live + free`.
+    /// It exceeds the number of slots by the ids issued ahead of their slots.
+    pub total: usize,
+
+    pub free: usize,
+    pub data_capacity: usize,
+    pub free_list_capacity: usize,
+}
+
+/// Full copy of the storage contents made by `CacheStorage::save_state`.
+#[derive(Debug, Clone)]
+pub struct StorageState<T> {
+    data: Vec<T>,
+    id_cache: IdCache,
+    insertion_seqs: Option<Vec<u64>>,
+    next_insertion_seq: u64,
+    uninitialized: HashSet<Id>,
+    unfilled: HashSet<Id>,
+    dirty: HashSet<Id>,
+}
+
 pub struct CacheStorage<T> {
     data: Vec<T>,
     id_cache: IdCache,
+    growth: Growth,
+    auto_trim: bool,
+    drop_on_remove: Option<fn() -> T>,
+    insertion_seqs: Option<Vec<u64>>,
+    next_insertion_seq: u64,
+    unfilled: HashSet<Id>,
+    dirty: HashSet<Id>,
+    uninitialized: HashSet<Id>,
+    owner: u64,
 }
 
 impl<T> CacheStorage<T> {
@@ -15,207 +94,2712 @@ impl<T> CacheStorage<T> {
         Self {
             data: Vec::default(),
             id_cache: IdCache::new(),
+            growth: Growth::default(),
+            auto_trim: false,
+            drop_on_remove: None,
+            insertion_seqs: None,
+            next_insertion_seq: 0,
+            unfilled: HashSet::new(),
+            dirty: HashSet::new(),
+            uninitialized: HashSet::new(),
+            owner: new_owner(),
         }
     }
 
+    /// Reserves `capacity` slots and pre-issues ids `0..capacity` as free ids,
+    /// see `IdCache::with_capacity`.
+    /// It makes `try_insert` succeed for the first `capacity` elements.
+    /// See `with_data_capacity` to only allocate.
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
             data: Vec::with_capacity(capacity),
             id_cache: IdCache::with_capacity(capacity),
+            growth: Growth::default(),
+            auto_trim: false,
+            drop_on_remove: None,
+            insertion_seqs: None,
+            next_insertion_seq: 0,
+            unfilled: HashSet::new(),
+            dirty: HashSet::new(),
+            uninitialized: HashSet::new(),
+            owner: new_owner(),
+        }
+    }
+
+    /// Allocates `capacity` slots and room for as many free ids without issuing any,
+    /// so no ids are free and `top_id` stays 0.
+    pub fn with_data_capacity(capacity: usize) -> Self {
+        Self {
+            data: Vec::with_capacity(capacity),
+            id_cache: IdCache::with_free_list_capacity(capacity),
+            growth: Growth::default(),
+            auto_trim: false,
+            drop_on_remove: None,
+            insertion_seqs: None,
+            next_insertion_seq: 0,
+            unfilled: HashSet::new(),
+            dirty: HashSet::new(),
+            uninitialized: HashSet::new(),
+            owner: new_owner(),
+        }
+    }
+
+    /// Same as `with_capacity`, but returns an error instead of aborting
+    /// if the memory can't be allocated.
+    pub fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError> {
+        let mut data = Vec::new();
+        data.try_reserve_exact(capacity)?;
+
+        Ok(Self {
+            data,
+            id_cache: IdCache::try_with_capacity(capacity)?,
+            growth: Growth::default(),
+            auto_trim: false,
+            drop_on_remove: None,
+            insertion_seqs: None,
+            next_insertion_seq: 0,
+            unfilled: HashSet::new(),
+            dirty: HashSet::new(),
+            uninitialized: HashSet::new(),
+            owner: new_owner(),
+        })
+    }
+
+    /// Issues `additional` new free ids and reserves the slots for them,
+    /// so the next inserts don't reallocate until these ids are used.
+    ///
+    /// # Note
+    /// The storage grows as `Vec` does:
+    /// an allocation at least doubles the capacity, so inserts are amortized O(1).
+    /// `grow` doesn't make the following inserts faster,
+    /// since they take the issued ids from the free list instead of new ones.
+    pub fn grow(&mut self, additional: usize) {
+        self.id_cache.grow(additional);
+        self.data.reserve(self.id_cache.top_id() - self.data.len());
+    }
+
+    /// Creates a storage where the element at index `i` has id `i`.
+    pub fn from_vec(data: Vec<T>) -> Self {
+        Self {
+            id_cache: IdCache::with_top_id(data.len()),
+            data,
+            growth: Growth::default(),
+            auto_trim: false,
+            drop_on_remove: None,
+            insertion_seqs: None,
+            next_insertion_seq: 0,
+            unfilled: HashSet::new(),
+            dirty: HashSet::new(),
+            uninitialized: HashSet::new(),
+            owner: new_owner(),
         }
     }
 
-    pub fn insert(&mut self, new_data: T) -> Id {
+    /// When enabled, `remove` of the element with the highest id
+    /// shrinks the storage instead of caching the id,
+    /// cascading through any trailing free ids.
+    /// Disabled by default.
+    pub fn set_auto_trim(&mut self, auto_trim: bool) {
+        self.auto_trim = auto_trim;
+    }
+
+    /// When enabled, every slot stores the sequence number of its last insertion,
+    /// so `iter_by_insertion` can yield elements independently of id reuse.
+    /// It costs extra 8 bytes per slot.
+    /// Live elements present at the moment of enabling are ordered by id.
+    /// Disabled by default.
+    pub fn set_track_insertion(&mut self, track_insertion: bool) {
+        self.insertion_seqs = if track_insertion {
+            let seqs = (self.next_insertion_seq..).take(self.data.len()).collect();
+            self.next_insertion_seq += self.data.len() as u64;
+
+            Some(seqs)
+        } else {
+            None
+        };
+    }
+
+    pub fn set_growth(&mut self, growth: Growth) {
+        self.growth = growth;
+    }
+
+    /// Returns a `Handle` of the new element, see `Handle::index` for the raw id.
+    pub fn insert(&mut self, new_data: T) -> Handle {
+        Handle::new(self.insert_id(new_data), self.owner)
+    }
+
+    fn insert_id(&mut self, new_data: T) -> Id {
+        if let Growth::Fixed(additional) = self.growth {
+            if self.id_cache.free_ids_num() == 0 {
+                self.id_cache.grow(additional);
+                self.data.reserve_exact(self.id_cache.top_id() - self.data.len());
+            }
+        }
+
         let id = self.id_cache.acquire_id();
-        self.insert_with_id(id, new_data);
+        self.place(id, new_data);
 
         id
     }
 
+    /// Inserts `new_data` at the lowest free id regardless of the reuse order,
+    /// or at a new id if there are no free ids. It is O(free ids).
+    pub fn insert_at_lowest(&mut self, new_data: T) -> Id {
+        match self.id_cache.free_ids.iter().min().copied() {
+            Some(id) => {
+                self.insert_with_id(id, new_data);
+                id
+            },
+            None => self.insert_id(new_data),
+        }
+    }
+
+    /// Inserts `new_data` and returns its id along with a reference to it.
+    pub fn insert_mut(&mut self, new_data: T) -> (Id, &mut T) {
+        let id = self.insert_id(new_data);
+
+        (id, &mut self.data[index(id)])
+    }
+
     pub fn try_insert(&mut self, new_data: T) -> Option<Id> {
         self.id_cache.try_acquire_id().map(|id| {
-            self.insert_with_id(id, new_data);
+            self.place(id, new_data);
             id
         })
     }
 
+    /// The same as `try_insert`, but the error carries the number of issued ids.
+    pub fn insert_bounded(&mut self, new_data: T) -> Result<Id, CapacityError> {
+        let capacity = self.id_cache.top_id();
+
+        self.try_insert(new_data).ok_or(CapacityError { capacity })
+    }
+
+    /// Inserts the items while there are free ids (see `try_insert`).
+    /// Returns the items that didn't fit.
+    pub fn extend_bounded<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Vec<T> {
+        let mut iter = iter.into_iter();
+
+        for item in &mut iter {
+            let id = match self.id_cache.try_acquire_id() {
+                Some(id) => id,
+                None => {
+                    let mut rest = vec![item];
+                    rest.extend(iter);
+                    return rest;
+                }
+            };
+
+            self.place(id, item);
+        }
+
+        vec![]
+    }
+
+    /// Places `new_data` at `id` and marks the `id` as acquired:
+    /// a free `id` is removed from the free list,
+    /// ids skipped above the last allocated one become free.
+    ///
+    /// # Panics
+    /// If `id` is greater than the number of slots.
     pub fn insert_with_id(&mut self, id: Id, new_data: T) {
+        if index(id) <= self.data.len() {
+            self.id_cache.acquire_exact_id(id);
+        }
+
+        self.place(id, new_data);
+    }
+
+    /// The same as `insert_with_id`, but returns an error instead of panicking
+    /// if `id` is greater than the number of slots.
+    pub fn try_insert_with_id(&mut self, id: Id, new_data: T) -> Result<(), InsertError> {
+        if index(id) > self.data.len() {
+            return Err(InsertError {
+                id,
+                valid_range: 0..to_id(self.data.len() + 1),
+            });
+        }
+
+        self.insert_with_id(id, new_data);
+
+        Ok(())
+    }
+
+    /// Makes the storage `new_len` slots long like `Vec::resize_with`.
+    /// Growing inserts `f(id)` at ids from the current number of slots up to `new_len`.
+    /// Shrinking removes the live elements from `new_len` on and trims the slots,
+    /// along with the free ids right below `new_len`.
+    pub fn resize_with(&mut self, new_len: usize, mut f: impl FnMut(Id) -> T) {
+        let len = self.data.len();
+        if new_len >= len {
+            for id in (len..new_len).map(to_id) {
+                self.insert_with_id(id, f(id));
+            }
+
+            return;
+        }
+
+        let free_ids = self.free_id_mask();
+        for id in (new_len..len).map(to_id).filter(|id| !free_ids.contains(id)) {
+            self.id_cache.release_id(id);
+            self.forget_slot_flags(id);
+        }
+
+        self.id_cache.trim_free_tail();
+        self.data.truncate(self.id_cache.top_id().min(new_len));
+        self.forget_uninitialized_above_len();
+
+        if let Some(seqs) = &mut self.insertion_seqs {
+            seqs.truncate(self.data.len());
+        }
+    }
+
+    fn place(&mut self, id: Id, new_data: T) {
+        for flags in [&mut self.unfilled, &mut self.dirty, &mut self.uninitialized] {
+            if !flags.is_empty() {
+                flags.remove(&id);
+            }
+        }
+
         let len = self.data.len();
-        if id == len {
+        if index(id) == len {
             self.data.push(new_data);
-        } else if id < len {
-            self.data[id] = new_data;
+        } else if index(id) < len {
+            self.data[index(id)] = new_data;
         } else {
-            panic!("`id` is out of valid range");
+            panic!("id {} is out of valid range 0..{}", id, len + 1);
+        }
+
+        if let Some(seqs) = &mut self.insertion_seqs {
+            let seq = self.next_insertion_seq;
+            self.next_insertion_seq += 1;
+
+            if index(id) == len {
+                seqs.push(seq);
+            } else {
+                seqs[index(id)] = seq;
+            }
         }
     }
 
-    pub fn get(&self, id: Id) -> &T {
-        &self.data[id]
+    /// Returns the free id the next `insert` reuses,
+    /// or `None` if it will take a fresh one.
+    pub fn next_free_id(&self) -> Option<Id> {
+        self.id_cache.free_ids.back().copied()
     }
 
-    pub fn get_mut(&mut self, id: Id) -> &mut T {
-        &mut self.data[id]
+    /// Checks whether `id` refers to a live element.
+    /// It is O(free ids), see `live_view` for repeated checks.
+    pub fn is_live(&self, id: Id) -> bool {
+        index(id) < self.data.len() && !self.id_cache.free_ids.contains(&id)
     }
 
-    /// # Panics
-    /// [DEBUG CFG]
-    /// * If `id` is greater than the last allocated id.
-    /// * If `id` was already released
-    pub fn remove(&mut self, id: Id) {
-        self.id_cache.release_id(id);
+    pub fn stats(&self) -> StorageStats {
+        let total = self.id_cache.top_id();
+        let free = self.id_cache.free_ids_num();
+
+        StorageStats {
+            live: total - free,
+            total,
+            free,
+            data_capacity: self.data.capacity(),
+            free_list_capacity: self.id_cache.free_ids.capacity(),
+        }
     }
 
-    /// # Safety
-    /// `ids` must contain only unique elements.
-    /// If `ids` contain duplicates - behavior is undefined.
-    ///
-    /// # Panics
-    /// When some `id` from the `ids` is greater than the last allocated id.
-    pub unsafe fn remove_chunk<I: IntoIterator<Item = Id>>(&mut self, ids: I) {
-        self.id_cache.release_ids(ids);
+    /// Checks whether an element was ever inserted at `id`, regardless of liveness.
+    /// The ids issued ahead of the slots (see `with_capacity` and `Growth::Fixed`)
+    /// and the slots backfilled with `T::default()` by `extend_with_ids`, `from_sparse`
+    /// and `warm` are not initialized until an element is inserted at them,
+    /// and `get_checked` rejects them.
+    pub fn is_initialized(&self, id: Id) -> bool {
+        index(id) < self.data.len() && !self.uninitialized.contains(&id)
     }
 
-    /// # Safety
-    /// It is safe to call this function,
-    /// but several removed elements may still stay in the collection,
-    /// so the corresponding ids were released.
-    pub unsafe fn iter(&self) -> impl Iterator<Item = (Id, &T)> {
-        self.data.iter().enumerate()
+    fn forget_uninitialized_above_len(&mut self) {
+        let len = self.data.len();
+        self.uninitialized.retain(|&id| index(id) < len);
     }
 
-    /// # Safety
-    /// It is safe to call this function,
-    /// but several removed elements may still stay in the vector.
-    pub unsafe fn into_vec(self) -> Vec<T> {
-        self.data
+    /// Tells whether `id` was never issued, is live or is freed.
+    /// It is O(free ids) like `is_live`.
+    pub fn classify(&self, id: Id) -> IdState {
+        if index(id) >= self.id_cache.top_id() {
+            IdState::NeverIssued
+        } else if self.id_cache.free_ids.contains(&id) {
+            IdState::Freed
+        } else {
+            IdState::Live
+        }
     }
-}
 
-impl<T> Extend<T> for CacheStorage<T> {
-    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
-        for item in iter {
-            self.insert(item);
+    /// Snapshots the free list once for cheap repeated liveness checks.
+    pub fn live_view(&self) -> LiveView<'_, T> {
+        LiveView::new(&self.data, self.id_cache.free_ids.iter().copied())
+    }
+
+    /// Lazily yields the live value or `None` for every id.
+    /// The free ids are sorted once, so it is O((F + M) log F) over M ids and F free ids,
+    /// regardless of the number of slots.
+    pub fn get_batch<'a, I>(&'a self, ids: I) -> impl Iterator<Item = Option<&'a T>> + 'a
+    where
+        I: IntoIterator<Item = Id>,
+        I::IntoIter: 'a
+    {
+        let free_ids = self.sorted_free_ids();
+
+        ids.into_iter().map(move |id| {
+            if free_ids.contains(&id) {
+                None
+            } else {
+                self.data.get(index(id))
+            }
+        })
+    }
+
+    /// Returns the `ids` that are out of range or freed, in their original order.
+    /// The free ids are sorted once, so it is O((F + M) log F) over M ids and F free ids,
+    /// regardless of the number of slots.
+    pub fn invalid_ids<I: IntoIterator<Item = Id>>(&self, ids: I) -> Vec<Id> {
+        let free_ids = self.sorted_free_ids();
+
+        ids.into_iter()
+            .filter(|id| index(*id) >= self.data.len() || free_ids.contains(id))
+            .collect()
+    }
+
+    /// Returns the lowest live id.
+    /// It skips the run of free ids at the start of the sorted free ids,
+    /// so it is O(F log F) for F free ids, regardless of the number of slots.
+    pub fn first_live_id(&self) -> Option<Id> {
+        let mut start = 0;
+        for &free_id in self.sorted_free_ids().0.iter() {
+            if index(free_id) != start {
+                break;
+            }
+
+            start += 1;
+        }
+
+        if start < self.data.len() {
+            Some(to_id(start))
+        } else {
+            None
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::CacheStorage;
+    /// Returns the highest live id, see `first_live_id`.
+    pub fn max_live_id(&self) -> Option<Id> {
+        let len = self.data.len();
 
-    #[test]
-    fn test_cache_storage() {
-        macro_rules! collect_data {
-            ($storage:expr) => {
-                unsafe {
-                    $storage
-                        .iter()
-                        .map(|(id, data)| (id, data.clone()))
-                        .collect::<Vec<_>>()
-                }
-            };
+        let mut end = len;
+        let free_ids = self.sorted_free_ids();
+        for &free_id in free_ids.0.iter().rev().skip_while(|&&id| index(id) >= len) {
+            if index(free_id) + 1 != end {
+                break;
+            }
+
+            end -= 1;
         }
 
-        let mut storage: CacheStorage<usize> = CacheStorage::new();
+        end.checked_sub(1).map(to_id)
+    }
 
-        assert_eq!(storage.data.len(), 0);
-        assert_eq!(collect_data![storage], vec![]);
+    /// Returns the live element with the lowest id, see `first_live_id`.
+    pub fn first(&self) -> Option<(Id, &T)> {
+        self.first_live_id().map(|id| (id, &self.data[index(id)]))
+    }
 
-        let first_id = storage.insert(42);
-        assert_eq!(first_id, 0);
-        assert_eq!(storage.data.len(), 1);
-        assert_eq!(*storage.get(first_id), 42);
-        *storage.get_mut(first_id) *= 2;
-        assert_eq!(*storage.get(first_id), 42 * 2);
-        assert_eq!(collect_data![storage], vec![(first_id, 42 * 2)]);
+    /// Returns the live element with the highest id, see `max_live_id`.
+    pub fn last(&self) -> Option<(Id, &T)> {
+        self.max_live_id().map(|id| (id, &self.data[index(id)]))
+    }
 
-        let second_id = storage.insert(111);
-        assert_eq!(second_id, 1);
-        assert_eq!(storage.data.len(), 2);
-        assert_eq!(*storage.get(second_id), 111);
-        *storage.get_mut(second_id) *= 2;
-        assert_eq!(*storage.get(second_id), 111 * 2);
-        assert_eq!(
-            collect_data![storage],
-            vec![(first_id, 42 * 2), (second_id, 111 * 2)]
-        );
+    /// Checks whether both storages have exactly the same live ids.
+    pub fn same_ids<U>(&self, other: &CacheStorage<U>) -> bool {
+        self.live_entries()
+            .map(|(id, _)| id)
+            .eq(other.live_entries().map(|(id, _)| id))
+    }
 
-        storage.remove(first_id);
-        assert_eq!(storage.data.len(), 2);
-        assert_eq!(
-            collect_data![storage],
-            vec![(first_id, 42 * 2), (second_id, 111 * 2)]
-        );
+    /// Verifies the `IdCache` invariants and that the cache agrees with the slots:
+    /// there are no slots above the issued ids and every id without a slot is free.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        self.id_cache.check_invariants()?;
 
-        let first_id = storage.insert(10);
-        assert_eq!(first_id, 0);
-        assert_eq!(storage.data.len(), 2);
-        assert_eq!(*storage.get(first_id), 10);
-        *storage.get_mut(first_id) *= 2;
-        assert_eq!(*storage.get(first_id), 10 * 2);
-        assert_eq!(
-            collect_data![storage],
-            vec![(first_id, 10 * 2), (second_id, 111 * 2)]
-        );
+        let top_id = self.id_cache.top_id();
+        if self.data.len() > top_id {
+            return Err(format!(
+                "{} slots, but only ids 0..{} were issued",
+                self.data.len(),
+                top_id
+            ));
+        }
 
-        let storage = CacheStorage::<i32>::with_capacity(10);
-        assert_eq!(storage.data.capacity(), 10);
-        assert_eq!(storage.data.len(), 0);
-        assert_eq!(collect_data![storage], vec![]);
+        let free_ids = self.free_id_mask();
+        match (self.data.len()..top_id).map(to_id).find(|id| !free_ids.contains(id)) {
+            Some(id) => Err(format!("id {} has no slot, but is not free", id)),
+            None => Ok(()),
+        }
     }
 
-    #[test]
-    fn test_cache_storage_try_insert() {
-        let mut storage = CacheStorage::with_capacity(3);
-        let id = storage.try_insert(0);
-        assert!(id.is_some());
-        assert_eq!(*storage.get(id.unwrap()), 0);
+    /// Returns the ids whose liveness disagrees with the `expected` mask.
+    /// Entries missing from the mask are treated as not live.
+    pub fn diff_occupancy(&self, expected: &[bool]) -> Vec<Id> {
+        let free_ids = self.free_id_mask();
+        let len = self.data.len().max(expected.len());
 
-        let id = storage.try_insert(1);
-        assert!(id.is_some());
-        assert_eq!(*storage.get(id.unwrap()), 1);
+        (0..len)
+            .map(to_id)
+            .filter(|&id| {
+                let is_live = index(id) < self.data.len() && !free_ids.contains(&id);
+                let is_expected = expected.get(index(id)).copied().unwrap_or(false);
 
-        let id = storage.try_insert(2);
-        assert!(id.is_some());
-        assert_eq!(*storage.get(id.unwrap()), 2);
-        let last_id = id.unwrap();
+                is_live != is_expected
+            })
+            .collect()
+    }
 
-        let id = storage.try_insert(3);
-        assert!(id.is_none());
+    /// Packs the liveness into 64-bit words, `ceil(top_id / 64)` of them.
+    /// Bit `i % 64` of word `i / 64` is set when id `i` is live,
+    /// i.e. the least significant bit stands for the lowest id.
+    pub fn live_bitmap(&self) -> Vec<u64> {
+        let mut bitmap = vec![0u64; (self.id_cache.top_id() + 63) / 64];
 
-        storage.remove(last_id);
+        let len = self.data.len();
+        for word in bitmap.iter_mut().take(len / 64) {
+            *word = u64::MAX;
+        }
+        if len % 64 != 0 {
+            bitmap[len / 64] = (1 << (len % 64)) - 1;
+        }
 
-        let id = storage.try_insert(3);
-        assert!(id.is_some());
-        assert_eq!(*storage.get(id.unwrap()), 3);
+        for &id in self.id_cache.free_ids.iter() {
+            bitmap[index(id) / 64] &= !(1 << (index(id) % 64));
+        }
+
+        bitmap
     }
 
-    #[test]
-    fn test_cache_storage_into_vec() {
-        let mut storage = CacheStorage::new();
-        let range = 0..5;
-        let expected = range.clone().collect::<Vec<_>>();
+    /// Rebuilds the free list from scratch: ids `0..len` of the slots are issued
+    /// and the ids of the slots rejected by `is_live` become free.
+    /// The free ids are acquired lowest first.
+    pub fn rebuild_free_list(&mut self, is_live: impl Fn(Id, &T) -> bool) {
+        self.id_cache.reset_to_top_id(self.data.len());
 
-        for i in range {
-            storage.insert(i);
+        let data = &self.data;
+        self.id_cache.free_ids.extend(
+            (0..data.len())
+                .rev()
+                .map(to_id)
+                .filter(|&id| !is_live(id, &data[index(id)]))
+        );
+
+        let free_ids = self.free_id_mask();
+        self.unfilled.retain(|id| !free_ids.contains(id));
+        self.dirty.retain(|id| !free_ids.contains(id));
+    }
+
+    /// Returns contiguous ranges of free ids sorted ascending.
+    pub fn free_runs(&self) -> Vec<Range<Id>> {
+        let mut free_ids = Vec::from(self.id_cache.free_ids.clone());
+        free_ids.sort_unstable();
+
+        let mut runs: Vec<Range<Id>> = vec![];
+        for id in free_ids {
+            match runs.last_mut() {
+                Some(run) if run.end == id => run.end += 1,
+                _ => runs.push(id..id + 1),
+            }
         }
 
-        let stored = unsafe { storage.into_vec() };
+        runs
+    }
 
-        assert_eq!(stored, expected);
+    /// Yields the maximal runs of consecutive live elements as slices
+    /// along with the id of the first element of each run, in ascending id order.
+    pub fn live_slices(&self) -> impl Iterator<Item = (Id, &[T])> {
+        let data = &self.data;
+        let mut start = 0;
+
+        self.free_runs()
+            .into_iter()
+            .map(|run| index(run.start)..index(run.end))
+            .chain(iter::once(data.len()..data.len()))
+            .filter_map(move |run| {
+                let live = start..run.start.min(data.len());
+                start = run.end;
+
+                if live.is_empty() {
+                    None
+                } else {
+                    Some((to_id(live.start), &data[live]))
+                }
+            })
     }
 
-    #[test]
-    fn test_cache_storage_extend() {
-        let mut storage = CacheStorage::with_capacity(5);
-        storage.extend(vec![1, 2, 3]);
+    /// Maps the lengths of the `free_runs` to the numbers of such runs.
+    pub fn free_run_histogram(&self) -> BTreeMap<usize, usize> {
+        let mut histogram = BTreeMap::new();
+        for run in self.free_runs() {
+            *histogram.entry(run.len()).or_insert(0) += 1;
+        }
 
-        assert_eq!(storage.data, vec![1, 2, 3]);
-        assert_eq!(storage.id_cache.free_ids, vec![4, 3]);
+        histogram
+    }
 
-        storage.extend(vec![4, 5, 6]);
-        assert_eq!(storage.data, vec![1, 2, 3, 4, 5, 6]);
-        assert_eq!(storage.id_cache.free_ids, vec![]);
+    /// Accepts a raw `Id` or a `Handle` returned by `insert`.
+    ///
+    /// # Panics
+    /// * If `key` is a handle of another storage
+    /// * [DEBUG CFG] If `id` is reserved by `reserve_id` and not filled yet
+    pub fn get<K: Key>(&self, key: K) -> &T {
+        let id = key.id_in(self.owner);
+        self.debug_assert_filled(id);
+        &self.data[index(id)]
+    }
+
+    /// Accepts a raw `Id` or a `Handle` returned by `insert`.
+    ///
+    /// # Panics
+    /// * If `key` is a handle of another storage
+    /// * [DEBUG CFG] If `id` is reserved by `reserve_id` and not filled yet
+    pub fn get_mut<K: Key>(&mut self, key: K) -> &mut T {
+        let id = key.id_in(self.owner);
+        self.debug_assert_filled(id);
+        &mut self.data[index(id)]
+    }
+
+    /// Mutates the element with `f` and marks it dirty, see `is_dirty`.
+    ///
+    /// # Panics
+    /// [DEBUG CFG]
+    /// * If `id` is reserved by `reserve_id` and not filled yet
+    pub fn modify<R>(&mut self, id: Id, f: impl FnOnce(&mut T) -> R) -> R {
+        let result = f(self.get_mut(id));
+        self.dirty.insert(id);
+
+        result
+    }
+
+    /// Checks whether the element was changed by `modify` since it was inserted
+    /// or since the last `clear_dirty`.
+    /// The changes through `get_mut` are not tracked.
+    pub fn is_dirty(&self, id: Id) -> bool {
+        self.dirty.contains(&id)
+    }
+
+    pub fn clear_dirty(&mut self, id: Id) {
+        self.dirty.remove(&id);
+    }
+
+    /// Returns `None` if `id` is out of range, freed or not initialized (see `is_initialized`).
+    ///
+    /// # Panics
+    /// [DEBUG CFG]
+    /// * If `id` is reserved by `reserve_id` and not filled yet
+    pub fn get_checked(&self, id: Id) -> Option<&T> {
+        if self.classify(id) == IdState::Live && !self.uninitialized.contains(&id) {
+            self.debug_assert_filled(id);
+            Some(&self.data[index(id)])
+        } else {
+            None
+        }
+    }
+
+    /// Completes the element reserved by `reserve_id`.
+    ///
+    /// # Panics
+    /// * If `id` is not live
+    ///
+    /// [DEBUG CFG]
+    /// * If `id` is not reserved
+    pub fn fill(&mut self, id: Id, value: T) {
+        assert!(self.is_live(id), "id {} is not live", id);
+        debug_assert!(self.unfilled.contains(&id), "id {} is not reserved", id);
+
+        self.place(id, value);
+    }
+
+    /// Drops the `reserve_id` and `modify` marks of a released id.
+    fn forget_slot_flags(&mut self, id: Id) {
+        self.unfilled.remove(&id);
+        self.dirty.remove(&id);
+    }
+
+    fn debug_assert_filled(&self, id: Id) {
+        debug_assert!(
+            !self.unfilled.contains(&id),
+            "id {} is reserved but not filled",
+            id
+        );
+    }
+
+    /// Returns mutable references to the live elements of all `ids` at once.
+    /// Every id is checked before any reference is taken.
+    pub fn try_get_disjoint_mut<const N: usize>(
+        &mut self,
+        ids: [Id; N]
+    ) -> Result<[&mut T; N], DisjointError> {
+        for (i, &id) in ids.iter().enumerate() {
+            if !self.is_live(id) {
+                return Err(DisjointError::Invalid(id));
+            }
+
+            if ids[..i].contains(&id) {
+                return Err(DisjointError::Duplicate(id));
+            }
+        }
+
+        let mut order = [(0, 0); N];
+        for (position, &id) in ids.iter().enumerate() {
+            order[position] = (index(id), position);
+        }
+        order.sort_unstable();
+
+        let mut objs: [Option<&mut T>; N] = [(); N].map(|_| None);
+        let mut rest = &mut self.data[..];
+        let mut offset = 0;
+        for &(slot, position) in order.iter() {
+            let (obj, tail) = mem::take(&mut rest)[slot - offset..]
+                .split_first_mut()
+                .expect("ids are checked to be in range");
+
+            objs[position] = Some(obj);
+            rest = tail;
+            offset = slot + 1;
+        }
+
+        Ok(objs.map(|obj| obj.expect("ids are checked to be unique")))
+    }
+
+    /// Returns the live value or `default` if `id` is out of range or freed.
+    pub fn get_or<'a>(&'a self, id: Id, default: &'a T) -> &'a T {
+        self.get_checked(id).unwrap_or(default)
+    }
+
+    /// Returns a clone of the live value or `None` if `id` is out of range or freed.
+    pub fn get_cloned(&self, id: Id) -> Option<T>
+    where
+        T: Clone
+    {
+        self.get_checked(id).cloned()
+    }
+
+    /// Accepts a raw `Id` or a `Handle` returned by `insert`, which is consumed.
+    ///
+    /// # Panics
+    /// * If `key` is a handle of another storage
+    /// * [DEBUG CFG] If `id` is greater than the last allocated id.
+    /// * [DEBUG CFG] If `id` was already released
+    pub fn remove<K: OwnedKey>(&mut self, key: K) {
+        let id = key.into_id_in(self.owner);
+        self.id_cache.release_id(id);
+        self.forget_slot_flags(id);
+
+        if let Some(default) = self.drop_on_remove {
+            self.data[index(id)] = default();
+        }
+
+        if self.auto_trim && index(id) + 1 == self.id_cache.top_id() {
+            self.id_cache.trim_free_tail();
+            self.data.truncate(self.id_cache.top_id());
+            self.forget_uninitialized_above_len();
+
+            if let Some(seqs) = &mut self.insertion_seqs {
+                seqs.truncate(self.id_cache.top_id());
+            }
+        }
+    }
+
+    /// Removes every live element whose id matches `predicate`.
+    /// Returns the number of removed elements.
+    pub fn remove_by_id<P: FnMut(Id) -> bool>(&mut self, mut predicate: P) -> usize {
+        let ids: Vec<Id> = self.live_entries()
+            .map(|(id, _)| id)
+            .filter(|&id| predicate(id))
+            .collect();
+
+        for &id in ids.iter() {
+            self.remove(id);
+        }
+
+        ids.len()
+    }
+
+    /// # Safety
+    /// `ids` must contain only unique elements.
+    /// If `ids` contain duplicates - behavior is undefined.
+    ///
+    /// # Panics
+    /// When some `id` from the `ids` is greater than the last allocated id.
+    pub unsafe fn remove_chunk<I: IntoIterator<Item = Id>>(&mut self, ids: I) {
+        let unfilled = &mut self.unfilled;
+        let dirty = &mut self.dirty;
+
+        self.id_cache.release_ids(ids.into_iter().inspect(|id| {
+            unfilled.remove(id);
+            dirty.remove(id);
+        }));
+    }
+
+    /// Yields live elements only, in ascending id order,
+    /// like `slab::Slab::iter` does.
+    ///
+    /// # Note
+    /// `slab::Slab` operations map to this storage as follows:
+    /// * `Slab::insert` - `insert`
+    /// * `Slab::contains` - `is_live`
+    /// * `Slab::get` / `Slab::get_mut` - `get` / `get_mut` (no liveness check)
+    /// * `Slab::remove` - `remove` (the value is not returned)
+    /// * `Slab::iter` - `iter_occupied`
+    pub fn iter_occupied(&self) -> impl Iterator<Item = (Id, &T)> {
+        self.live_entries()
+    }
+
+    /// Moves the live elements into a `slab::Slab` under the same ids.
+    /// The freed slots below the highest live id become vacant entries.
+    ///
+    /// # Note
+    /// The slab reuses its vacant entries in its own order,
+    /// so its next key may differ from `next_free_id`.
+    #[cfg(feature = "slab")]
+    pub fn into_slab(self) -> slab::Slab<T> {
+        let free_ids = self.free_id_mask();
+
+        enumerate_ids(self.data.into_iter())
+            .filter(|(id, _)| !free_ids.contains(id))
+            .map(|(id, obj)| (index(id), obj))
+            .collect()
+    }
+
+    /// Yields live elements in the order they were inserted,
+    /// see `set_track_insertion`.
+    /// Without the tracking the elements are yielded in id order.
+    pub fn iter_by_insertion(&self) -> impl Iterator<Item = (Id, &T)> {
+        let mut entries: Vec<_> = self.live_entries().collect();
+        if let Some(seqs) = &self.insertion_seqs {
+            entries.sort_by_key(|&(id, _)| seqs[index(id)]);
+        }
+
+        entries.into_iter()
+    }
+
+    /// Yields the live elements sorted by `key`, the equal ones in ascending id order.
+    /// Allocates a temporary vector of the live entries.
+    pub fn iter_sorted_by_key<K, F>(&self, mut key: F) -> impl Iterator<Item = (Id, &T)>
+    where
+        K: Ord,
+        F: FnMut(&T) -> K
+    {
+        let mut entries: Vec<_> = self.live_entries().collect();
+        entries.sort_by_key(|&(_, obj)| key(obj));
+
+        entries.into_iter()
+    }
+
+    /// Applies `f` to the live elements in ascending id order
+    /// and returns the first `Some` result, the later elements are not visited.
+    pub fn find_map_mut<B, F>(&mut self, mut f: F) -> Option<B>
+    where
+        F: FnMut(Id, &mut T) -> Option<B>
+    {
+        let free_ids = self.free_id_mask();
+
+        enumerate_ids(self.data.iter_mut())
+            .filter(|(id, _)| !free_ids.contains(id))
+            .find_map(|(id, obj)| f(id, obj))
+    }
+
+    /// Drops every element and frees the ids, so the next `insert` returns 0 again.
+    /// The slots and the free list keep their capacity.
+    /// The settings like `set_growth` or `set_track_insertion` are kept.
+    pub fn reset(&mut self) {
+        self.data.clear();
+        self.id_cache.reset();
+        self.unfilled.clear();
+        self.dirty.clear();
+        self.uninitialized.clear();
+        if let Some(seqs) = &mut self.insertion_seqs {
+            seqs.clear();
+        }
+    }
+
+    /// Yields the live elements by value in ascending id order and empties the storage.
+    /// The capacity of the slots is kept, so the storage can be refilled without reallocation.
+    /// The ids are reset, the next `insert` returns 0 again.
+    /// The elements not consumed by the iterator are dropped.
+    pub fn drain_keep_alloc(&mut self) -> impl Iterator<Item = (Id, T)> + '_ {
+        let free_ids = self.free_id_mask();
+
+        self.id_cache.reset();
+        self.unfilled.clear();
+        self.dirty.clear();
+        self.uninitialized.clear();
+        if let Some(seqs) = &mut self.insertion_seqs {
+            seqs.clear();
+        }
+
+        enumerate_ids(self.data.drain(..)).filter(move |(id, _)| !free_ids.contains(id))
+    }
+
+    /// Yields every slot with its state, the safe alternative to `iter`.
+    /// The values of the `Freed` slots are stale.
+    pub fn iter_all(&self) -> impl Iterator<Item = (Id, SlotState, &T)> {
+        let free_ids = self.free_id_mask();
+
+        enumerate_ids(self.data.iter()).map(move |(id, obj)| {
+            let state = if free_ids.contains(&id) {
+                SlotState::Freed
+            } else {
+                SlotState::Live
+            };
+
+            (id, state, obj)
+        })
+    }
+
+    /// # Safety
+    /// It is safe to call this function,
+    /// but several removed elements may still stay in the collection,
+    /// so the corresponding ids were released.
+    pub unsafe fn iter(&self) -> impl Iterator<Item = (Id, &T)> {
+        enumerate_ids(self.data.iter())
+    }
+
+    /// # Safety
+    /// It is safe to call this function,
+    /// but several removed elements may still stay in the vector.
+    pub unsafe fn into_vec(self) -> Vec<T> {
+        self.data
+    }
+
+    /// Returns a cursor pointing to the first live element.
+    pub fn cursor_mut(&mut self) -> Cursor<'_, T> {
+        let mut cursor = Cursor {
+            free_ids: self.free_id_mask(),
+            storage: self,
+            slot: 0,
+        };
+        cursor.skip_freed();
+
+        cursor
+    }
+
+    /// Removes the freed slots, so the live ids become dense `0..len`.
+    /// Surviving elements keep their relative order.
+    /// Returns the permutation where `perm[old_id]` is the new id for every id
+    /// below the former `top_id`, or `usize::MAX` for the removed ones.
+    pub fn compact_perm(&mut self) -> Vec<usize> {
+        self.compact_into(drop)
+    }
+
+    /// The same as `compact_perm`, but returns the map from old ids to new ones
+    /// and the stale values of the removed freed slots in ascending id order.
+    pub fn compact_collecting(&mut self) -> (HashMap<Id, Id>, Vec<T>) {
+        let mut removed = vec![];
+        let remap = perm_to_remap(self.compact_into(|obj| removed.push(obj)));
+
+        (remap, removed)
+    }
+
+    fn compact_into<F: FnMut(T)>(&mut self, mut on_removed: F) -> Vec<usize> {
+        let free_ids = self.free_id_mask();
+        let mut perm = vec![usize::MAX; self.id_cache.top_id()];
+
+        let data = mem::take(&mut self.data);
+        let seqs = self.insertion_seqs.take();
+        let mut new_seqs = seqs.as_ref().map(|_| vec![]);
+
+        for (old_id, obj) in enumerate_ids(data.into_iter()) {
+            if free_ids.contains(&old_id) {
+                on_removed(obj);
+                continue;
+            }
+
+            perm[index(old_id)] = self.data.len();
+            self.data.push(obj);
+
+            if let (Some(seqs), Some(new_seqs)) = (&seqs, &mut new_seqs) {
+                new_seqs.push(seqs[index(old_id)]);
+            }
+        }
+
+        self.insertion_seqs = new_seqs;
+        self.id_cache.reset_to_top_id(self.data.len());
+        self.unfilled = remap_ids(&self.unfilled, &perm);
+        self.dirty = remap_ids(&self.dirty, &perm);
+        self.uninitialized = remap_ids(&self.uninitialized, &perm);
+
+        perm
+    }
+
+    /// The same as `compact_perm`, but then calls `fixup` on every surviving element
+    /// with the complete map from old ids to new ones,
+    /// so the elements can rewrite the ids they refer to.
+    pub fn compact_with<F: FnMut(&mut T, &HashMap<Id, Id>)>(&mut self, mut fixup: F) {
+        let remap = perm_to_remap(self.compact_perm());
+
+        for obj in self.data.iter_mut() {
+            fixup(obj, &remap);
+        }
+    }
+
+    /// Returns immutable storage without freed elements
+    /// and the map from old ids to the new ones.
+    /// Surviving elements keep their relative order.
+    pub fn freeze(self) -> (FrozenStorage<T>, HashMap<Id, Id>) {
+        let free_ids = self.free_id_mask();
+
+        let mut remap = HashMap::new();
+        let data: Vec<_> = enumerate_ids(enumerate_ids(self.data.into_iter())
+            .filter(|(id, _)| !free_ids.contains(id)))
+            .map(|(new_id, (old_id, obj))| {
+                remap.insert(old_id, new_id);
+                obj
+            })
+            .collect();
+
+        (FrozenStorage::from_boxed_slice(data.into_boxed_slice()), remap)
+    }
+
+    fn sorted_free_ids(&self) -> SortedFreeIds {
+        let mut free_ids = Vec::from(self.id_cache.free_ids.clone());
+        free_ids.sort_unstable();
+
+        SortedFreeIds(free_ids)
+    }
+
+    fn free_id_mask(&self) -> FreeIdMask {
+        let mut mask = vec![false; self.id_cache.top_id()];
+        for &id in self.id_cache.free_ids.iter() {
+            mask[index(id)] = true;
+        }
+
+        FreeIdMask(mask)
+    }
+
+    pub(crate) fn live_entries(&self) -> impl Iterator<Item = (Id, &T)> {
+        let free_ids = self.free_id_mask();
+
+        enumerate_ids(self.data.iter())
+            .filter(move |(id, _)| !free_ids.contains(id))
+    }
+}
+
+impl<T: Clone> CacheStorage<T> {
+    /// Copies the slots and the free list for a later `restore_state`.
+    /// The freed slots are copied as well, since they still hold values.
+    pub fn save_state(&self) -> StorageState<T> {
+        StorageState {
+            data: self.data.clone(),
+            id_cache: self.id_cache.clone(),
+            insertion_seqs: self.insertion_seqs.clone(),
+            next_insertion_seq: self.next_insertion_seq,
+            uninitialized: self.uninitialized.clone(),
+            unfilled: self.unfilled.clone(),
+            dirty: self.dirty.clone(),
+        }
+    }
+
+    /// Overwrites the contents with the saved `state`.
+    /// The settings like `set_growth` or `set_auto_trim` are kept.
+    pub fn restore_state(&mut self, state: StorageState<T>) {
+        self.data = state.data;
+        self.id_cache = state.id_cache;
+        self.insertion_seqs = state.insertion_seqs;
+        self.next_insertion_seq = state.next_insertion_seq;
+        self.uninitialized = state.uninitialized;
+        self.unfilled = state.unfilled;
+        self.dirty = state.dirty;
+    }
+}
+
+impl<T: Default + Clone> CacheStorage<T> {
+    /// Returns a clone of the live value or `T::default()`
+    /// if `id` is out of range or freed.
+    pub fn get_or_default(&self, id: Id) -> T {
+        self.get_checked(id).cloned().unwrap_or_default()
+    }
+
+    /// Same as `get_or_default`, but borrows the live value instead of cloning it.
+    pub fn get_or_default_cow(&self, id: Id) -> Cow<'_, T> {
+        match self.get_checked(id) {
+            Some(obj) => Cow::Borrowed(obj),
+            None => Cow::Owned(T::default()),
+        }
+    }
+}
+
+impl<T: Default> CacheStorage<T> {
+    /// Fills the slots up to `up_to` with `T::default()` and frees their ids,
+    /// so the memory is allocated and touched before the first inserts.
+    /// The live elements are not affected, `top_id` becomes at least `up_to`
+    /// and the new free ids are acquired in ascending order after the ones free already.
+    pub fn warm(&mut self, up_to: usize) {
+        let len = self.data.len();
+        if up_to <= len {
+            return;
+        }
+
+        self.data.reserve_exact(up_to - len);
+        self.data.resize_with(up_to, T::default);
+        self.uninitialized.extend((len..up_to).map(to_id));
+
+        if let Some(seqs) = &mut self.insertion_seqs {
+            seqs.resize(up_to, 0);
+        }
+
+        let top_id = self.id_cache.top_id();
+        if up_to > top_id {
+            self.id_cache.grow(up_to - top_id);
+        }
+    }
+
+    /// Acquires an id for an element that doesn't exist yet,
+    /// the slot holds `T::default()` until `fill`.
+    /// Reading the element before `fill` panics in debug builds.
+    pub fn reserve_id(&mut self) -> Id {
+        let id = self.insert_id(T::default());
+        self.unfilled.insert(id);
+
+        id
+    }
+
+    /// Creates a storage where `Some` at index `i` becomes the live element with id `i`
+    /// and `None` slots become free ids (filled with `T::default()`).
+    /// Lower free ids are reused first.
+    pub fn from_sparse(sparse: Vec<Option<T>>) -> Self {
+        let mut free_ids = vec![];
+        let data = enumerate_ids(sparse.into_iter())
+            .map(|(id, obj)| obj.unwrap_or_else(|| {
+                free_ids.push(id);
+                T::default()
+            }))
+            .collect();
+
+        let mut storage = Self::from_vec(data);
+        storage.uninitialized = free_ids.iter().copied().collect();
+        free_ids.reverse();
+        storage.id_cache.free_ids = free_ids.into();
+
+        storage
+    }
+
+    /// Removes every live element matching `predicate` and returns them.
+    /// It is the same as fully consumed `drain_filter`.
+    pub fn remove_where<P: FnMut(&T) -> bool>(&mut self, mut predicate: P) -> Vec<(Id, T)> {
+        self.drain_filter(|obj| predicate(obj)).collect()
+    }
+
+    /// Places each value at its id, see `insert_with_id`.
+    /// The pairs may come in any order, for repeated ids the last value wins.
+    /// Slots of the ids missing in `iter` are filled with `T::default()` and freed.
+    pub fn extend_with_ids<I: IntoIterator<Item = (Id, T)>>(&mut self, iter: I) {
+        let mut pairs: Vec<_> = iter.into_iter().collect();
+        pairs.sort_by_key(|&(id, _)| id);
+
+        for (id, obj) in pairs {
+            while self.data.len() < index(id) {
+                let gap_id = to_id(self.data.len());
+                self.place(gap_id, T::default());
+                self.uninitialized.insert(gap_id);
+
+                if index(gap_id) >= self.id_cache.top_id() {
+                    self.id_cache.acquire_exact_id(gap_id);
+                    self.id_cache.release_id(gap_id);
+                }
+            }
+
+            self.insert_with_id(id, obj);
+        }
+    }
+
+    /// When enabled, `remove` replaces the removed element with `T::default()`,
+    /// so the element is dropped immediately instead of staying in the slot
+    /// until the id is reused.
+    /// Disabled by default.
+    pub fn set_drop_on_remove(&mut self, drop_on_remove: bool) {
+        self.drop_on_remove = if drop_on_remove {
+            Some(T::default)
+        } else {
+            None
+        };
+    }
+
+    /// Lazily removes and yields live elements for which `predicate` returns `true`.
+    /// A removed element is replaced with `T::default()` in the storage,
+    /// its id is released.
+    ///
+    /// Elements that were not reached by the iterator stay in the storage.
+    pub fn drain_filter<'a, P>(&'a mut self, mut predicate: P) -> impl Iterator<Item = (Id, T)> + 'a
+    where
+        P: FnMut(&mut T) -> bool + 'a
+    {
+        let free_ids = self.free_id_mask();
+        let mut next_id = 0;
+
+        iter::from_fn(move || {
+            while next_id < self.data.len() {
+                let slot = next_id;
+                let id = to_id(slot);
+                next_id += 1;
+
+                if free_ids.contains(&id) || !predicate(&mut self.data[slot]) {
+                    continue;
+                }
+
+                let obj = mem::take(&mut self.data[slot]);
+                self.remove(id);
+
+                return Some((id, obj));
+            }
+
+            None
+        })
+    }
+}
+
+fn remap_ids(ids: &HashSet<Id>, perm: &[usize]) -> HashSet<Id> {
+    ids.iter()
+        .map(|&id| perm[index(id)])
+        .filter(|&new_id| new_id != usize::MAX)
+        .map(to_id)
+        .collect()
+}
+
+fn perm_to_remap(perm: Vec<usize>) -> HashMap<Id, Id> {
+    perm.into_iter()
+        .enumerate()
+        .filter(|&(_, new_id)| new_id != usize::MAX)
+        .map(|(old_id, new_id)| (to_id(old_id), to_id(new_id)))
+        .collect()
+}
+
+/// Removes the live element `from_id` from `from`, replacing it with `T::default()`,
+/// and inserts it into `to`. Returns its new id in `to`.
+///
+/// # Panics
+/// If `from_id` is not live in `from`.
+pub fn move_element<T: Default>(
+    from: &mut CacheStorage<T>,
+    from_id: Id,
+    to: &mut CacheStorage<T>
+) -> Id {
+    assert!(from.is_live(from_id), "id {} is not live", from_id);
+
+    let obj = mem::take(&mut from.data[index(from_id)]);
+    from.remove(from_id);
+
+    to.insert_id(obj)
+}
+
+impl CacheStorage<Box<dyn Any>> {
+    /// Returns the live value downcast to `T`,
+    /// or `None` if it has another type or `id` is out of range or freed.
+    pub fn get_as<T: 'static>(&self, id: Id) -> Option<&T> {
+        self.get_checked(id)?.downcast_ref()
+    }
+}
+
+/// Snapshot of the free list for liveness checks over all the slots.
+/// It is a mask over the issued ids rather than a `HashSet`,
+/// since hashing dominates the checks otherwise (see `benches/liveness.rs`).
+/// Building it is O(issued ids), see `SortedFreeIds` for a few checks.
+struct FreeIdMask(Vec<bool>);
+
+impl FreeIdMask {
+    fn contains(&self, id: &Id) -> bool {
+        self.0.get(index(*id)).copied().unwrap_or(false)
+    }
+}
+
+/// Snapshot of the free list sized to it rather than to the issued ids,
+/// for checks of a few ids.
+struct SortedFreeIds(Vec<Id>);
+
+impl SortedFreeIds {
+    fn contains(&self, id: &Id) -> bool {
+        self.0.binary_search(id).is_ok()
+    }
+}
+
+/// Traverses live elements in ascending id order
+/// and allows removing the current one without invalidation.
+pub struct Cursor<'a, T> {
+    storage: &'a mut CacheStorage<T>,
+    free_ids: FreeIdMask,
+    slot: usize,
+}
+
+impl<T> Cursor<'_, T> {
+    pub fn current(&mut self) -> Option<(Id, &mut T)> {
+        if self.slot < self.storage.data.len() {
+            Some((to_id(self.slot), &mut self.storage.data[self.slot]))
+        } else {
+            None
+        }
+    }
+
+    /// Moves to the next live element.
+    pub fn advance(&mut self) {
+        self.slot += 1;
+        self.skip_freed();
+    }
+
+    /// Removes the current element and moves to the next live one.
+    /// Returns the removed id.
+    pub fn remove_current(&mut self) -> Option<Id> {
+        if self.slot >= self.storage.data.len() {
+            return None;
+        }
+
+        let id = to_id(self.slot);
+        self.storage.remove(id);
+        self.advance();
+
+        Some(id)
+    }
+
+    fn skip_freed(&mut self) {
+        while self.slot < self.storage.data.len()
+            && self.free_ids.contains(&to_id(self.slot))
+        {
+            self.slot += 1;
+        }
+    }
+}
+
+impl<T> Default for CacheStorage<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Storages are equal when they have the same live `(id, value)` pairs.
+/// Stale values of the freed slots are ignored.
+impl<T: PartialEq> PartialEq for CacheStorage<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.live_entries().eq(other.live_entries())
+    }
+}
+
+impl<T: Eq> Eq for CacheStorage<T> {}
+
+/// Hashes only the live `(id, value)` pairs in ascending id order,
+/// consistent with `PartialEq`.
+impl<T: Hash> Hash for CacheStorage<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut live_num = 0usize;
+        for (id, obj) in self.live_entries() {
+            id.hash(state);
+            obj.hash(state);
+            live_num += 1;
+        }
+
+        live_num.hash(state);
+    }
+}
+
+impl<T> Extend<T> for CacheStorage<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            self.insert(item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{
+            CacheStorage, CapacityError, DisjointError, Growth, IdState, InsertError, SlotState, index,
+            move_element, to_id,
+        },
+        std::{any::Any, borrow::Cow, collections::HashSet, mem},
+    };
+
+    #[test]
+    fn test_cache_storage() {
+        macro_rules! collect_data {
+            ($storage:expr) => {
+                unsafe {
+                    $storage
+                        .iter()
+                        .map(|(id, data)| (id, data.clone()))
+                        .collect::<Vec<_>>()
+                }
+            };
+        }
+
+        let mut storage: CacheStorage<usize> = CacheStorage::new();
+
+        assert_eq!(storage.data.len(), 0);
+        assert_eq!(collect_data![storage], vec![]);
+
+        let first_id = storage.insert(42).id();
+        assert_eq!(first_id, 0);
+        assert_eq!(storage.data.len(), 1);
+        assert_eq!(*storage.get(first_id), 42);
+        *storage.get_mut(first_id) *= 2;
+        assert_eq!(*storage.get(first_id), 42 * 2);
+        assert_eq!(collect_data![storage], vec![(first_id, 42 * 2)]);
+
+        let second_id = storage.insert(111).id();
+        assert_eq!(second_id, 1);
+        assert_eq!(storage.data.len(), 2);
+        assert_eq!(*storage.get(second_id), 111);
+        *storage.get_mut(second_id) *= 2;
+        assert_eq!(*storage.get(second_id), 111 * 2);
+        assert_eq!(
+            collect_data![storage],
+            vec![(first_id, 42 * 2), (second_id, 111 * 2)]
+        );
+
+        storage.remove(first_id);
+        assert_eq!(storage.data.len(), 2);
+        assert_eq!(
+            collect_data![storage],
+            vec![(first_id, 42 * 2), (second_id, 111 * 2)]
+        );
+
+        let first_id = storage.insert(10).id();
+        assert_eq!(first_id, 0);
+        assert_eq!(storage.data.len(), 2);
+        assert_eq!(*storage.get(first_id), 10);
+        *storage.get_mut(first_id) *= 2;
+        assert_eq!(*storage.get(first_id), 10 * 2);
+        assert_eq!(
+            collect_data![storage],
+            vec![(first_id, 10 * 2), (second_id, 111 * 2)]
+        );
+
+        let storage = CacheStorage::<i32>::with_capacity(10);
+        assert_eq!(storage.data.capacity(), 10);
+        assert_eq!(storage.data.len(), 0);
+        assert_eq!(collect_data![storage], vec![]);
+    }
+
+    #[test]
+    fn test_cache_storage_try_with_capacity() {
+        let storage = CacheStorage::<i32>::try_with_capacity(10).unwrap();
+        assert!(storage.data.capacity() >= 10);
+        assert_eq!(storage.id_cache.free_ids_num(), 10);
+
+        assert!(CacheStorage::<i32>::try_with_capacity(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_cache_storage_grow() {
+        let mut storage = CacheStorage::new();
+        storage.extend(0..3);
+        storage.remove(1);
+
+        storage.grow(100);
+        assert!(storage.data.capacity() >= 103);
+        assert_eq!(storage.id_cache.free_ids_num(), 101);
+
+        let capacity = storage.data.capacity();
+        let ids: Vec<_> = (0..101).map(|i| storage.insert(i).index()).collect();
+        assert_eq!(ids[0], 1);
+        assert_eq!(ids[1..], (3..103).collect::<Vec<_>>()[..]);
+        assert_eq!(storage.data.capacity(), capacity);
+    }
+
+    #[test]
+    fn test_cache_storage_iter_occupied() {
+        let mut storage = CacheStorage::new();
+        assert_eq!(storage.iter_occupied().count(), 0);
+
+        storage.extend(vec!['a', 'b', 'c', 'd']);
+        storage.remove(0);
+        storage.remove(2);
+
+        assert_eq!(
+            storage.iter_occupied().collect::<Vec<_>>(),
+            vec![(1, &'b'), (3, &'d')]
+        );
+    }
+
+    #[test]
+    fn test_cache_storage_get_checked() {
+        let mut storage = CacheStorage::new();
+        storage.extend(vec![String::from("a"), String::from("b")]);
+        storage.remove(0);
+
+        assert_eq!(storage.get_checked(0), None);
+        assert_eq!(storage.get_checked(1).map(String::as_str), Some("b"));
+        assert_eq!(storage.get_checked(2), None);
+
+        assert_eq!(storage.get_or_default(0), "");
+        assert_eq!(storage.get_or_default(1), "b");
+        assert_eq!(storage.get_or_default(100), "");
+
+        assert!(matches!(storage.get_or_default_cow(1), Cow::Borrowed(obj) if obj == "b"));
+        assert!(matches!(storage.get_or_default_cow(0), Cow::Owned(obj) if obj.is_empty()));
+    }
+
+    #[test]
+    fn test_cache_storage_fixed_growth() {
+        let mut storage = CacheStorage::new();
+        storage.set_growth(Growth::Fixed(4));
+
+        storage.insert(0);
+        assert_eq!(storage.data.capacity(), 4);
+        assert_eq!(storage.id_cache.top_id(), 4);
+        assert_eq!(storage.id_cache.free_ids, vec![3, 2, 1]);
+
+        storage.extend(1..5);
+        assert_eq!(storage.data.capacity(), 8);
+        assert_eq!(storage.id_cache.free_ids, vec![7, 6, 5]);
+        assert_eq!(storage.data, (0..5).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_cache_storage_insert_mut() {
+        let mut storage = CacheStorage::new();
+        storage.insert(String::from("a"));
+
+        let (id, obj) = storage.insert_mut(String::from("b"));
+        obj.push('c');
+        assert_eq!(id, 1);
+        assert_eq!(storage.get(id), "bc");
+    }
+
+    #[test]
+    fn test_cache_storage_handles() {
+        let mut storage = CacheStorage::new();
+        let first = storage.insert(String::from("a"));
+        let second = storage.insert(String::from("b"));
+        assert_eq!(first.index(), 0);
+        assert_eq!(second.index(), 1);
+
+        storage.get_mut(&second).push('c');
+        assert_eq!(storage.get(&second), "bc");
+        assert_eq!(storage.get(second.id()), "bc");
+
+        storage.remove(first);
+        assert!(!storage.is_live(0));
+
+        let third = storage.insert(String::from("d"));
+        assert_eq!(third.index(), 0);
+        assert_eq!(storage.get(&third), "d");
+    }
+
+    #[test]
+    #[should_panic(expected = "handle 0 belongs to another storage")]
+    fn test_cache_storage_handle_of_another_storage() {
+        let mut storage = CacheStorage::new();
+        let mut other = CacheStorage::new();
+        other.insert('a');
+
+        let handle = storage.insert('b');
+        other.get(&handle);
+    }
+
+    #[test]
+    fn test_cache_storage_get_batch() {
+        let mut storage = CacheStorage::new();
+        storage.extend(vec!['a', 'b', 'c']);
+        storage.remove(1);
+
+        assert_eq!(
+            storage.get_batch(vec![2, 1, 0, 3, 2]).collect::<Vec<_>>(),
+            vec![Some(&'c'), None, Some(&'a'), None, Some(&'c')]
+        );
+    }
+
+    #[test]
+    fn test_cache_storage_try_insert() {
+        let mut storage = CacheStorage::with_capacity(3);
+        let id = storage.try_insert(0);
+        assert!(id.is_some());
+        assert_eq!(*storage.get(id.unwrap()), 0);
+
+        let id = storage.try_insert(1);
+        assert!(id.is_some());
+        assert_eq!(*storage.get(id.unwrap()), 1);
+
+        let id = storage.try_insert(2);
+        assert!(id.is_some());
+        assert_eq!(*storage.get(id.unwrap()), 2);
+        let last_id = id.unwrap();
+
+        let id = storage.try_insert(3);
+        assert!(id.is_none());
+
+        storage.remove(last_id);
+
+        let id = storage.try_insert(3);
+        assert!(id.is_some());
+        assert_eq!(*storage.get(id.unwrap()), 3);
+    }
+
+    #[test]
+    fn test_cache_storage_auto_trim() {
+        let mut storage = CacheStorage::new();
+        storage.extend(0..4);
+        storage.remove(3);
+        assert_eq!(storage.data, vec![0, 1, 2, 3]);
+        assert_eq!(storage.id_cache.free_ids, vec![3]);
+
+        let mut storage = CacheStorage::new();
+        storage.set_auto_trim(true);
+        storage.extend(0..4);
+
+        storage.remove(3);
+        assert_eq!(storage.data, vec![0, 1, 2]);
+        assert!(storage.id_cache.free_ids.is_empty());
+
+        storage.remove(1);
+        assert_eq!(storage.data, vec![0, 1, 2]);
+        assert_eq!(storage.id_cache.free_ids, vec![1]);
+
+        storage.remove(2);
+        assert_eq!(storage.data, vec![0]);
+        assert!(storage.id_cache.free_ids.is_empty());
+        assert_eq!(storage.id_cache.top_id(), 1);
+
+        assert_eq!(storage.insert(10).index(), 1);
+        assert_eq!(storage.data, vec![0, 10]);
+    }
+
+    #[test]
+    fn test_cache_storage_drain_filter() {
+        let mut storage = CacheStorage::new();
+        storage.extend(0..10);
+        storage.remove(4);
+
+        let mut seen = vec![];
+        let drained: Vec<_> = storage
+            .drain_filter(|obj| {
+                seen.push(*obj);
+                *obj % 2 == 0
+            })
+            .collect();
+
+        assert_eq!(drained, vec![(0, 0), (2, 2), (6, 6), (8, 8)]);
+        assert_eq!(seen, vec![0, 1, 2, 3, 5, 6, 7, 8, 9]);
+        assert_eq!(storage.id_cache.free_ids, vec![4, 0, 2, 6, 8]);
+
+        let drained: Vec<_> = storage.drain_filter(|obj| *obj == 7).take(1).collect();
+        assert_eq!(drained, vec![(7, 7)]);
+        assert_eq!(storage.id_cache.free_ids, vec![4, 0, 2, 6, 8, 7]);
+    }
+
+    #[test]
+    fn test_cache_storage_eq_hash() {
+        let mut first = CacheStorage::new();
+        first.extend(vec![1, 2, 3, 4]);
+        first.remove(3);
+        first.remove(1);
+
+        let mut second = CacheStorage::new();
+        second.extend(vec![1, 20, 3, 40]);
+        second.remove(1);
+        second.remove(3);
+
+        let mut third = CacheStorage::new();
+        third.extend(vec![1, 2, 3, 4]);
+        third.remove(1);
+
+        assert_ne!(first.data, second.data);
+        assert!(first == second);
+        assert!(first != third);
+
+        let mut set = HashSet::new();
+        set.insert(first);
+        set.insert(second);
+        set.insert(third);
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_cache_storage_extend_bounded() {
+        let mut storage = CacheStorage::with_capacity(3);
+
+        let rest = storage.extend_bounded(vec![1, 2]);
+        assert!(rest.is_empty());
+        assert_eq!(storage.data, vec![1, 2]);
+
+        let rest = storage.extend_bounded(vec![3, 4, 5]);
+        assert_eq!(rest, vec![4, 5]);
+        assert_eq!(storage.data, vec![1, 2, 3]);
+
+        let rest = storage.extend_bounded(vec![6]);
+        assert_eq!(rest, vec![6]);
+
+        storage.remove(1);
+        let rest = storage.extend_bounded(rest);
+        assert!(rest.is_empty());
+        assert_eq!(storage.data, vec![1, 6, 3]);
+    }
+
+    #[test]
+    fn test_cache_storage_first_live_id() {
+        let mut storage = CacheStorage::new();
+        assert_eq!(storage.first_live_id(), None);
+
+        storage.extend(0..4);
+        assert_eq!(storage.first_live_id(), Some(0));
+
+        storage.remove(0);
+        storage.remove(1);
+        assert_eq!(storage.first_live_id(), Some(2));
+
+        storage.remove(3);
+        storage.remove(2);
+        assert_eq!(storage.first_live_id(), None);
+    }
+
+    #[test]
+    fn test_cache_storage_max_live_id() {
+        let mut storage = CacheStorage::new();
+        assert_eq!(storage.max_live_id(), None);
+
+        storage.extend(0..5);
+        assert_eq!(storage.max_live_id(), Some(4));
+
+        storage.remove(4);
+        storage.remove(3);
+        assert_eq!(storage.id_cache.top_id(), 5);
+        assert_eq!(storage.max_live_id(), Some(2));
+
+        storage.remove(0);
+        storage.remove(1);
+        storage.remove(2);
+        assert_eq!(storage.max_live_id(), None);
+    }
+
+    #[test]
+    fn test_cache_storage_same_ids() {
+        let mut names = CacheStorage::new();
+        let mut ages = CacheStorage::new();
+        assert!(names.same_ids(&ages));
+
+        names.extend(vec!["a", "b", "c"]);
+        ages.extend(vec![1, 2, 3]);
+        assert!(names.same_ids(&ages));
+
+        ages.remove(1);
+        assert!(!names.same_ids(&ages));
+        assert!(!ages.same_ids(&names));
+
+        names.remove(1);
+        assert!(names.same_ids(&ages));
+    }
+
+    #[test]
+    fn test_cache_storage_check_invariants() {
+        let mut storage = CacheStorage::with_capacity(4);
+        assert_eq!(storage.check_invariants(), Ok(()));
+
+        storage.extend(0..6);
+        storage.remove(2);
+        assert_eq!(storage.check_invariants(), Ok(()));
+
+        storage.data.truncate(4);
+        assert_eq!(
+            storage.check_invariants(),
+            Err("id 4 has no slot, but is not free".to_string())
+        );
+
+        storage.data.extend(vec![4, 5, 6]);
+        assert_eq!(
+            storage.check_invariants(),
+            Err("7 slots, but only ids 0..6 were issued".to_string())
+        );
+    }
+
+    #[test]
+    fn test_cache_storage_free_runs() {
+        let mut storage = CacheStorage::new();
+        storage.extend(0..12);
+        assert!(storage.free_runs().is_empty());
+
+        for &id in [7, 2, 11, 3, 0, 8, 4, 9].iter() {
+            storage.remove(id);
+        }
+
+        assert_eq!(storage.free_runs(), vec![0..1, 2..5, 7..10, 11..12]);
+    }
+
+    #[test]
+    fn test_cache_storage_drop_on_remove() {
+        let mut storage = CacheStorage::new();
+        storage.extend(vec![vec![1; 16], vec![2; 16]]);
+        storage.remove(0);
+        assert_eq!(storage.data[0], vec![1; 16]);
+
+        storage.set_drop_on_remove(true);
+        storage.remove(1);
+        assert_eq!(storage.data[1], vec![]);
+        assert_eq!(storage.data[1].capacity(), 0);
+
+        storage.set_drop_on_remove(false);
+        let id = storage.insert(vec![3]).id();
+        storage.remove(id);
+        assert_eq!(storage.data[index(id)], vec![3]);
+    }
+
+    #[test]
+    fn test_cache_storage_insert_with_id() {
+        let mut storage = CacheStorage::new();
+        storage.extend(vec![0, 1, 2]);
+        storage.remove(1);
+
+        storage.insert_with_id(1, 10);
+        assert!(storage.id_cache.free_ids.is_empty());
+        assert_eq!(storage.insert(3).index(), 3);
+
+        storage.insert_with_id(4, 4);
+        assert_eq!(storage.insert(5).index(), 5);
+        assert_eq!(storage.data, vec![0, 10, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_cache_storage_extend_with_ids() {
+        let mut replayed = CacheStorage::new();
+        replayed.extend_with_ids(vec![(3, 'd'), (0, 'a'), (5, 'x'), (1, 'b'), (5, 'f')]);
+
+        let mut expected = CacheStorage::new();
+        expected.extend(vec!['a', 'b', 'c', 'd', 'e', 'f']);
+        expected.remove(2);
+        expected.remove(4);
+
+        assert!(replayed == expected);
+        assert_eq!(replayed.id_cache.top_id(), 6);
+        assert_eq!(replayed.free_runs(), vec![2..3, 4..5]);
+
+        replayed.extend_with_ids(vec![(8, 'i'), (2, 'c')]);
+        assert_eq!(replayed.free_runs(), vec![4..5, 6..8]);
+        assert_eq!(*replayed.get(8), 'i');
+        assert_eq!(*replayed.get(2), 'c');
+    }
+
+    #[test]
+    fn test_cache_storage_remove_where() {
+        let mut storage = CacheStorage::new();
+        storage.extend(vec![5, 1, 7, 3, 9]);
+        storage.remove(2);
+
+        let removed = storage.remove_where(|&obj| obj > 2);
+        assert_eq!(removed, vec![(0, 5), (3, 3), (4, 9)]);
+        assert_eq!(storage.id_cache.free_ids, vec![2, 0, 3, 4]);
+        assert_eq!(storage.first_live_id(), Some(1));
+        assert_eq!(storage.max_live_id(), Some(1));
+    }
+
+    #[test]
+    fn test_cache_storage_cursor_mut() {
+        let mut storage = CacheStorage::new();
+        storage.extend(0..8);
+        storage.remove(0);
+        storage.remove(5);
+
+        let mut visited = vec![];
+        let mut cursor = storage.cursor_mut();
+        while let Some((id, obj)) = cursor.current() {
+            visited.push(id);
+            *obj *= 10;
+
+            if id % 2 == 0 {
+                assert_eq!(cursor.remove_current(), Some(id));
+            } else {
+                cursor.advance();
+            }
+        }
+        assert_eq!(cursor.remove_current(), None);
+
+        assert_eq!(visited, vec![1, 2, 3, 4, 6, 7]);
+        assert_eq!(storage.live_entries().collect::<Vec<_>>(), vec![(1, &10), (3, &30), (7, &70)]);
+    }
+
+    #[test]
+    fn test_cache_storage_iter_by_insertion() {
+        let mut storage = CacheStorage::new();
+        storage.extend(vec!['a', 'b']);
+        storage.set_track_insertion(true);
+        storage.extend(vec!['c', 'd']);
+
+        storage.remove(1);
+        storage.remove(0);
+        storage.insert('e');
+        storage.insert('f');
+        storage.insert('g');
+
+        assert_eq!(
+            storage.iter_by_insertion().map(|(_, &obj)| obj).collect::<String>(),
+            "cdefg"
+        );
+        assert_eq!(
+            storage.iter_occupied().map(|(_, &obj)| obj).collect::<String>(),
+            "efcdg"
+        );
+
+        storage.set_track_insertion(false);
+        assert_eq!(
+            storage.iter_by_insertion().map(|(_, &obj)| obj).collect::<String>(),
+            "efcdg"
+        );
+    }
+
+    #[test]
+    fn test_cache_storage_into_vec() {
+        let mut storage = CacheStorage::new();
+        let range = 0..5;
+        let expected = range.clone().collect::<Vec<_>>();
+
+        for i in range {
+            storage.insert(i);
+        }
+
+        let stored = unsafe { storage.into_vec() };
+
+        assert_eq!(stored, expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "id 7 is out of valid range 0..4")]
+    fn test_cache_storage_insert_with_id_out_of_range() {
+        let mut storage = CacheStorage::new();
+        storage.extend(0..3);
+        storage.insert_with_id(7, 7);
+    }
+
+    #[test]
+    fn test_cache_storage_from_vec() {
+        let src = vec![5, 6, 7];
+        let mut storage = CacheStorage::from_vec(src.clone());
+        assert_eq!(storage.id_cache.top_id(), 3);
+        assert!(storage.id_cache.free_ids.is_empty());
+        assert_eq!(*storage.get(1), 6);
+        assert_eq!(storage.insert(8).index(), 3);
+
+        let stored = unsafe { CacheStorage::from_vec(src.clone()).into_vec() };
+        assert_eq!(stored, src);
+    }
+
+    #[test]
+    fn test_cache_storage_from_sparse() {
+        let mut storage = CacheStorage::from_sparse(vec![None, Some('b'), None, Some('d'), None]);
+        assert_eq!(storage.id_cache.top_id(), 5);
+        assert_eq!(storage.free_runs(), vec![0..1, 2..3, 4..5]);
+        assert_eq!(
+            storage.iter_occupied().collect::<Vec<_>>(),
+            vec![(1, &'b'), (3, &'d')]
+        );
+
+        assert_eq!(storage.insert('a').index(), 0);
+        assert_eq!(storage.insert('c').index(), 2);
+    }
+
+    #[test]
+    fn test_cache_storage_extend() {
+        let mut storage = CacheStorage::with_capacity(5);
+        storage.extend(vec![1, 2, 3]);
+
+        assert_eq!(storage.data, vec![1, 2, 3]);
+        assert_eq!(storage.id_cache.free_ids, vec![4, 3]);
+
+        storage.extend(vec![4, 5, 6]);
+        assert_eq!(storage.data, vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(storage.id_cache.free_ids, vec![]);
+    }
+
+    #[test]
+    fn test_cache_storage_first_last() {
+        let mut storage = CacheStorage::new();
+        assert_eq!(storage.first(), None);
+        assert_eq!(storage.last(), None);
+
+        for c in "abcde".chars() {
+            storage.insert(c);
+        }
+
+        assert_eq!(storage.first(), Some((0, &'a')));
+        assert_eq!(storage.last(), Some((4, &'e')));
+
+        storage.remove(0);
+        storage.remove(1);
+        storage.remove(4);
+        assert_eq!(storage.first(), Some((2, &'c')));
+        assert_eq!(storage.last(), Some((3, &'d')));
+
+        storage.remove(2);
+        storage.remove(3);
+        assert_eq!(storage.first(), None);
+        assert_eq!(storage.last(), None);
+    }
+
+    #[test]
+    fn test_cache_storage_get_cloned() {
+        let mut storage = CacheStorage::new();
+        let a = storage.insert(String::from("a")).id();
+        let b = storage.insert(String::from("b")).id();
+        storage.remove(a);
+
+        assert_eq!(storage.get_cloned(a), None);
+        assert_eq!(storage.get_cloned(b), Some(String::from("b")));
+        assert_eq!(storage.get_cloned(2), None);
+    }
+
+    #[test]
+    fn test_cache_storage_diff_occupancy() {
+        let mut storage = CacheStorage::new();
+        storage.extend(0..5);
+        storage.remove(1);
+        storage.remove(3);
+
+        assert!(storage.diff_occupancy(&[true, false, true, false, true]).is_empty());
+        assert_eq!(storage.diff_occupancy(&[true, true, true, false, false]), vec![1, 4]);
+        assert_eq!(storage.diff_occupancy(&[true, false, true]), vec![4]);
+        assert_eq!(
+            storage.diff_occupancy(&[true, false, true, false, true, false, true]),
+            vec![6]
+        );
+    }
+
+    #[test]
+    fn test_cache_storage_remove_by_id() {
+        let mut storage = CacheStorage::new();
+        storage.extend(0..10);
+        storage.remove(4);
+
+        assert_eq!(storage.remove_by_id(|id| id % 2 == 0), 4);
+        assert_eq!(
+            storage.iter_occupied().map(|(id, _)| id).collect::<Vec<_>>(),
+            vec![1, 3, 5, 7, 9]
+        );
+        assert!(storage.check_invariants().is_ok());
+
+        assert_eq!(storage.remove_by_id(|id| id % 2 == 0), 0);
+        assert_eq!(storage.insert(100).index(), 8);
+    }
+
+    #[test]
+    fn test_cache_storage_save_restore_state() {
+        let mut storage = CacheStorage::new();
+        storage.extend(vec!['a', 'b', 'c', 'd']);
+        storage.remove(1);
+
+        let state = storage.save_state();
+        let saved: Vec<_> = storage.iter_occupied().map(|(id, &obj)| (id, obj)).collect();
+
+        storage.remove(0);
+        storage.insert('x');
+        storage.insert('y');
+        *storage.get_mut(3) = 'z';
+
+        storage.restore_state(state);
+        assert_eq!(
+            storage.iter_occupied().map(|(id, &obj)| (id, obj)).collect::<Vec<_>>(),
+            saved
+        );
+        assert!(storage.check_invariants().is_ok());
+        assert_eq!(storage.insert('e').index(), 1);
+    }
+
+    #[test]
+    fn test_cache_storage_insert_bounded() {
+        let mut storage = CacheStorage::with_capacity(2);
+        assert_eq!(storage.insert_bounded('a'), Ok(0));
+        assert_eq!(storage.insert_bounded('b'), Ok(1));
+        assert_eq!(storage.insert_bounded('c'), Err(CapacityError { capacity: 2 }));
+        assert_eq!(CapacityError { capacity: 2 }.to_string(), "storage is full, capacity is 2");
+
+        storage.remove(0);
+        assert_eq!(storage.insert_bounded('c'), Ok(0));
+    }
+
+    #[test]
+    fn test_cache_storage_live_bitmap() {
+        let mut storage = CacheStorage::new();
+        assert!(storage.live_bitmap().is_empty());
+
+        storage.extend(0..130);
+        for &id in [0, 63, 64, 100].iter() {
+            storage.remove(id);
+        }
+        storage.grow(3);
+
+        let bitmap = storage.live_bitmap();
+        assert_eq!(bitmap.len(), 3);
+
+        let live_ids: Vec<_> = (0..bitmap.len() * 64)
+            .filter(|&i| bitmap[i / 64] & (1 << (i % 64)) != 0)
+            .collect();
+        let expected: Vec<_> = storage.iter_occupied().map(|(id, _)| index(id)).collect();
+        assert_eq!(live_ids, expected);
+        assert_eq!(live_ids.len(), 126);
+    }
+
+    #[test]
+    fn test_cache_storage_with_data_capacity() {
+        let mut storage = CacheStorage::with_data_capacity(10);
+        assert!(storage.data.capacity() >= 10);
+        assert_eq!(storage.id_cache.top_id(), 0);
+        assert_eq!(storage.id_cache.free_ids_num(), 0);
+        assert_eq!(storage.try_insert('a'), None);
+        assert_eq!(storage.insert('a').index(), 0);
+
+        let storage = CacheStorage::<char>::with_capacity(10);
+        assert_eq!(storage.id_cache.top_id(), 10);
+        assert_eq!(storage.id_cache.free_ids_num(), 10);
+    }
+
+    #[test]
+    fn test_cache_storage_get_or() {
+        let mut storage = CacheStorage::new();
+        let a = storage.insert(String::from("a")).id();
+        let b = storage.insert(String::from("b")).id();
+        storage.remove(a);
+
+        let default = String::from("default");
+        assert_eq!(storage.get_or(a, &default), "default");
+        assert_eq!(storage.get_or(b, &default), "b");
+        assert_eq!(storage.get_or(5, &default), "default");
+    }
+
+    #[test]
+    fn test_cache_storage_compact_perm() {
+        let mut storage = CacheStorage::new();
+        storage.extend(vec!['a', 'b', 'c', 'd', 'e', 'f']);
+        storage.remove(0);
+        storage.remove(3);
+        storage.remove(4);
+        storage.grow(2);
+
+        let perm = storage.compact_perm();
+        assert_eq!(perm, vec![usize::MAX, 0, 1, usize::MAX, usize::MAX, 2, usize::MAX, usize::MAX]);
+        assert_eq!(
+            storage.iter_occupied().map(|(id, &obj)| (id, obj)).collect::<Vec<_>>(),
+            vec![(0, 'b'), (1, 'c'), (2, 'f')]
+        );
+        assert!(storage.check_invariants().is_ok());
+        assert_eq!(storage.insert('g').index(), 3);
+
+        let parallel = ['a', 'b', 'c', 'd', 'e', 'f'];
+        let mut remapped = [' '; 3];
+        for (old_id, &new_id) in perm.iter().enumerate().take(parallel.len()) {
+            if new_id != usize::MAX {
+                remapped[new_id] = parallel[old_id];
+            }
+        }
+        assert_eq!(remapped, ['b', 'c', 'f']);
+    }
+
+    #[test]
+    fn test_cache_storage_try_get_disjoint_mut() {
+        let mut storage = CacheStorage::new();
+        storage.extend(vec![1, 2, 3, 4]);
+        storage.remove(2);
+
+        let [a, b] = storage.try_get_disjoint_mut([3, 0]).unwrap();
+        std::mem::swap(a, b);
+        assert_eq!(*storage.get(0), 4);
+        assert_eq!(*storage.get(3), 1);
+
+        let [a, b, c] = storage.try_get_disjoint_mut([1, 3, 0]).unwrap();
+        assert_eq!((*a, *b, *c), (2, 1, 4));
+
+        assert_eq!(storage.try_get_disjoint_mut([0, 1, 0]).err(), Some(DisjointError::Duplicate(0)));
+        assert_eq!(storage.try_get_disjoint_mut([0, 2]).err(), Some(DisjointError::Invalid(2)));
+        assert_eq!(storage.try_get_disjoint_mut([7]).err(), Some(DisjointError::Invalid(7)));
+    }
+
+    #[test]
+    fn test_cache_storage_compact_with() {
+        // Singly linked list: (value, next id).
+        let mut storage = CacheStorage::new();
+        let c = storage.insert(('c', None)).id();
+        let garbage = storage.insert(('x', None)).id();
+        let b = storage.insert(('b', Some(c))).id();
+        storage.insert(('a', Some(b)));
+        storage.remove(garbage);
+
+        storage.compact_with(|(_, next), remap| {
+            if let Some(next) = next {
+                *next = remap[next];
+            }
+        });
+
+        // The head moved from 3 to 2.
+        let mut values = vec![];
+        let mut next = Some(2);
+        while let Some(id) = next {
+            let (value, next_id) = *storage.get(id);
+            values.push(value);
+            next = next_id;
+        }
+
+        assert_eq!(values, vec!['a', 'b', 'c']);
+        assert_eq!(storage.iter_occupied().count(), 3);
+    }
+
+    #[test]
+    fn test_cache_storage_iter_all() {
+        let mut storage = CacheStorage::new();
+        storage.extend(vec!['a', 'b', 'c']);
+        storage.remove(1);
+        storage.grow(2);
+
+        assert_eq!(
+            storage.iter_all().map(|(id, state, &obj)| (id, state, obj)).collect::<Vec<_>>(),
+            vec![(0, SlotState::Live, 'a'), (1, SlotState::Freed, 'b'), (2, SlotState::Live, 'c')]
+        );
+    }
+
+    #[test]
+    fn test_cache_storage_rebuild_free_list() {
+        let mut storage = CacheStorage::new();
+        storage.extend(vec![1, 0, 3, 0, 0, 6]);
+        storage.remove(2);
+        storage.grow(4);
+
+        storage.rebuild_free_list(|_, &obj| obj != 0);
+        assert!(storage.check_invariants().is_ok());
+        assert_eq!(storage.id_cache.top_id(), 6);
+        assert_eq!(
+            storage.iter_occupied().map(|(id, _)| id).collect::<Vec<_>>(),
+            vec![0, 2, 5]
+        );
+
+        assert_eq!(storage.insert(7).index(), 1);
+        assert_eq!(storage.insert(8).index(), 3);
+    }
+
+    #[test]
+    fn test_cache_storage_try_insert_with_id() {
+        let mut storage = CacheStorage::new();
+        storage.extend(vec!['a', 'b']);
+        storage.remove(0);
+
+        assert_eq!(storage.try_insert_with_id(0, 'x'), Ok(()));
+        assert_eq!(storage.try_insert_with_id(2, 'c'), Ok(()));
+        assert!(storage.check_invariants().is_ok());
+        assert_eq!(
+            storage.iter_occupied().map(|(id, &obj)| (id, obj)).collect::<Vec<_>>(),
+            vec![(0, 'x'), (1, 'b'), (2, 'c')]
+        );
+
+        let err = storage.try_insert_with_id(5, 'f').unwrap_err();
+        assert_eq!(err, InsertError { id: 5, valid_range: 0..4 });
+        assert_eq!(err.to_string(), "id 5 is out of valid range 0..4");
+        assert_eq!(storage.iter_occupied().count(), 3);
+    }
+
+    #[test]
+    fn test_cache_storage_get_as() {
+        let mut storage: CacheStorage<Box<dyn Any>> = CacheStorage::new();
+        let number = storage.insert(Box::new(42u32)).id();
+        let text = storage.insert(Box::new(String::from("plugin"))).id();
+
+        assert_eq!(storage.get_as::<u32>(number), Some(&42));
+        assert_eq!(storage.get_as::<String>(number), None);
+        assert_eq!(storage.get_as::<String>(text).map(String::as_str), Some("plugin"));
+
+        storage.remove(number);
+        assert_eq!(storage.get_as::<u32>(number), None);
+        assert_eq!(storage.get_as::<u32>(5), None);
+    }
+
+    #[test]
+    fn test_cache_storage_find_map_mut() {
+        let mut storage = CacheStorage::new();
+        storage.extend(vec![1, 2, 3, 4, 5]);
+        storage.remove(1);
+
+        let mut visited = vec![];
+        let found = storage.find_map_mut(|id, obj| {
+            visited.push(id);
+            if *obj % 2 == 1 && *obj > 1 {
+                *obj *= 10;
+                Some(id)
+            } else {
+                None
+            }
+        });
+
+        assert_eq!(found, Some(2));
+        assert_eq!(visited, vec![0, 2]);
+        assert_eq!(*storage.get(2), 30);
+        assert_eq!(*storage.get(4), 5);
+        assert_eq!(storage.find_map_mut(|_, &mut obj| if obj > 100 { Some(obj) } else { None }), None);
+    }
+
+    #[test]
+    fn test_cache_storage_iter_sorted_by_key() {
+        let mut storage = CacheStorage::new();
+        storage.extend(vec![("d", 4), ("a", 1), ("x", 0), ("c", 3), ("b", 1)]);
+        storage.remove(2);
+
+        assert_eq!(
+            storage.iter_sorted_by_key(|&(_, rank)| rank).map(|(id, &(name, _))| (id, name)).collect::<Vec<_>>(),
+            vec![(1, "a"), (4, "b"), (3, "c"), (0, "d")]
+        );
+    }
+
+    #[test]
+    fn test_cache_storage_next_free_id() {
+        let mut storage = CacheStorage::new();
+        storage.extend(0..4);
+        assert_eq!(storage.next_free_id(), None);
+
+        storage.remove(1);
+        storage.remove(2);
+        assert_eq!(storage.next_free_id(), Some(2));
+        assert_eq!(storage.insert(10).index(), 2);
+        assert_eq!(storage.next_free_id(), Some(1));
+        assert_eq!(storage.insert(11).index(), 1);
+        assert_eq!(storage.next_free_id(), None);
+    }
+
+    #[test]
+    fn test_cache_storage_move_element() {
+        let mut from = CacheStorage::new();
+        let mut to = CacheStorage::new();
+        from.extend(vec![String::from("a"), String::from("b")]);
+        to.insert(String::from("x"));
+
+        let new_id = move_element(&mut from, 1, &mut to);
+        assert_eq!(new_id, 1);
+        assert_eq!(to.get_checked(new_id).map(String::as_str), Some("b"));
+        assert!(!from.is_live(1));
+        assert_eq!(from.iter_occupied().count(), 1);
+        assert!(from.check_invariants().is_ok());
+        assert!(to.check_invariants().is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "id 1 is not live")]
+    fn test_cache_storage_move_element_freed() {
+        let mut from = CacheStorage::new();
+        let mut to = CacheStorage::new();
+        from.extend(vec![1, 2]);
+        from.remove(1);
+
+        move_element(&mut from, 1, &mut to);
+    }
+
+    #[test]
+    fn test_cache_storage_free_run_histogram() {
+        let mut storage = CacheStorage::new();
+        assert!(storage.free_run_histogram().is_empty());
+
+        storage.extend(0..12);
+        for &id in [1, 3, 4, 6, 8, 9, 10].iter() {
+            storage.remove(id);
+        }
+
+        let histogram = storage.free_run_histogram();
+        assert_eq!(histogram.into_iter().collect::<Vec<_>>(), vec![(1, 2), (2, 1), (3, 1)]);
+    }
+
+    #[test]
+    fn test_cache_storage_drain_keep_alloc() {
+        let mut storage = CacheStorage::new();
+        storage.extend(vec!['a', 'b', 'c', 'd']);
+        storage.remove(1);
+        let capacity = storage.data.capacity();
+
+        let drained: Vec<_> = storage.drain_keep_alloc().collect();
+        assert_eq!(drained, vec![(0, 'a'), (2, 'c'), (3, 'd')]);
+        assert_eq!(storage.data.capacity(), capacity);
+        assert_eq!(storage.iter_occupied().count(), 0);
+        assert!(storage.check_invariants().is_ok());
+        assert_eq!(storage.insert('e').index(), 0);
+    }
+
+    #[test]
+    fn test_cache_storage_compact_collecting() {
+        let mut storage = CacheStorage::new();
+        storage.extend(vec![String::from("a"), String::from("b"), String::from("c"), String::from("d")]);
+        storage.remove(2);
+        storage.remove(0);
+        storage.grow(2);
+
+        let (remap, removed) = storage.compact_collecting();
+        assert_eq!(removed, vec![String::from("a"), String::from("c")]);
+        assert_eq!(remap, [(1, 0), (3, 1)].iter().copied().collect());
+        assert_eq!(storage.get_checked(0).map(String::as_str), Some("b"));
+        assert_eq!(storage.get_checked(1).map(String::as_str), Some("d"));
+        assert!(storage.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn test_cache_storage_resize_with() {
+        let mut storage = CacheStorage::new();
+        storage.resize_with(4, |id| index(id) * 10);
+        assert_eq!(
+            storage.iter_occupied().map(|(id, &obj)| (id, obj)).collect::<Vec<_>>(),
+            vec![(0, 0), (1, 10), (2, 20), (3, 30)]
+        );
+
+        storage.grow(2);
+        storage.resize_with(6, |id| index(id) * 10);
+        assert_eq!(storage.iter_occupied().count(), 6);
+        assert!(storage.check_invariants().is_ok());
+
+        storage.remove(1);
+        storage.resize_with(3, |_| unreachable!());
+        assert_eq!(
+            storage.iter_occupied().map(|(id, &obj)| (id, obj)).collect::<Vec<_>>(),
+            vec![(0, 0), (2, 20)]
+        );
+        assert!(storage.check_invariants().is_ok());
+        assert_eq!(storage.insert(100).index(), 1);
+        assert_eq!(storage.insert(100).index(), 3);
+
+        storage.remove(3);
+        storage.remove(2);
+        storage.resize_with(3, |_| unreachable!());
+        assert_eq!(storage.data.len(), 2);
+        assert!(storage.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn test_cache_storage_invalid_ids() {
+        let mut storage = CacheStorage::new();
+        for i in 0..5 {
+            storage.insert(i);
+        }
+        storage.remove(1);
+        storage.remove(3);
+
+        assert_eq!(storage.invalid_ids(vec![0, 1, 2, 3, 4, 5, 100, 1]), vec![1, 3, 5, 100, 1]);
+        assert!(storage.invalid_ids(vec![0, 2, 4]).is_empty());
+        assert!(storage.invalid_ids(None).is_empty());
+    }
+
+    #[cfg(feature = "slab")]
+    #[test]
+    fn test_cache_storage_into_slab() {
+        let mut storage = CacheStorage::new();
+        storage.extend(0..10);
+        for &id in [0, 3, 4, 7].iter() {
+            storage.remove(id);
+        }
+
+        let live: Vec<_> = storage.iter_occupied()
+            .map(|(id, &obj)| (index(id), obj))
+            .collect();
+
+        let slab = storage.into_slab();
+        assert_eq!(slab.iter().map(|(key, &obj)| (key, obj)).collect::<Vec<_>>(), live);
+        assert!(!slab.contains(0) && !slab.contains(3) && !slab.contains(7));
+        assert_eq!(slab.len(), 6);
+    }
+
+    #[test]
+    fn test_cache_storage_insert_at_lowest() {
+        let mut storage = CacheStorage::new();
+        storage.extend(0..8);
+        storage.remove(5);
+        storage.remove(2);
+        storage.remove(6);
+
+        assert_eq!(storage.insert_at_lowest(20), 2);
+        assert_eq!(storage.insert_at_lowest(50), 5);
+        assert_eq!(storage.insert(60).index(), 6);
+        assert_eq!(storage.insert_at_lowest(80), 8);
+        assert_eq!(*storage.get(2), 20);
+        assert_eq!(*storage.get(5), 50);
+        assert!(storage.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn test_cache_storage_is_initialized() {
+        let mut storage = CacheStorage::with_capacity(4);
+        assert!((0..4).all(|id| !storage.is_initialized(id)));
+        assert_eq!(storage.get_checked(0), None);
+
+        assert_eq!(storage.insert("a").index(), 0);
+        assert!(storage.is_initialized(0));
+        assert!(!storage.is_initialized(1));
+        assert_eq!(storage.get_checked(1), None);
+
+        storage.remove(0);
+        assert!(storage.is_initialized(0));
+        assert_eq!(storage.get_checked(0), None);
+
+        let mut storage = CacheStorage::new();
+        storage.set_growth(Growth::Fixed(8));
+        storage.insert(1);
+        assert!(storage.is_initialized(0));
+        assert!(!storage.is_initialized(5));
+        assert_eq!(storage.get_checked(5), None);
+        storage.insert_with_id(1, 2);
+        assert!(storage.is_initialized(1));
+        assert_eq!(storage.get_checked(1), Some(&2));
+    }
+
+    #[test]
+    fn test_cache_storage_stats() {
+        let mut storage = CacheStorage::with_capacity(4);
+        let stats = storage.stats();
+        assert_eq!((stats.live, stats.free, stats.total), (0, 4, 4));
+        assert!(stats.data_capacity >= 4 && stats.free_list_capacity >= 4);
+
+        storage.extend(0..10);
+        storage.remove(3);
+        storage.remove(8);
+
+        let stats = storage.stats();
+        assert_eq!(stats.live + stats.free, stats.total);
+        assert_eq!((stats.live, stats.free, stats.total), (8, 2, 10));
+        assert_eq!(stats.live, storage.iter_occupied().count());
+        assert!(stats.data_capacity >= stats.total);
+        assert!(stats.free_list_capacity >= stats.free);
+    }
+
+    #[test]
+    fn test_cache_storage_reserve_id() {
+        let mut storage = CacheStorage::new();
+        let parent = storage.reserve_id();
+        let child = storage.insert((vec![], Some(parent))).id();
+        storage.fill(parent, (vec![child], None));
+
+        assert_eq!(storage.get(parent).0, vec![child]);
+        assert_eq!(storage.get(child).1, Some(parent));
+
+        // The reservation follows the element when the storage is compacted
+        let reserved = storage.reserve_id();
+        storage.remove(child);
+        let reserved = to_id(storage.compact_perm()[index(reserved)]);
+        assert_eq!(reserved, 1);
+
+        storage.fill(reserved, (vec![], None));
+        assert_eq!(storage.get_checked(reserved), Some(&(vec![], None)));
+
+        // A pending reservation survives a save / restore round trip
+        let reserved = storage.reserve_id();
+        let state = storage.save_state();
+        storage.fill(reserved, (vec![], None));
+        storage.restore_state(state);
+        storage.fill(reserved, (vec![child], None));
+        assert_eq!(storage.get(reserved).0, vec![child]);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "id 0 is reserved but not filled")]
+    fn test_cache_storage_reserve_id_read_before_fill() {
+        let mut storage = CacheStorage::<u32>::new();
+        let id = storage.reserve_id();
+        storage.get(id);
+    }
+
+    #[test]
+    fn test_cache_storage_live_slices() {
+        let mut storage = CacheStorage::new();
+        assert_eq!(storage.live_slices().count(), 0);
+
+        storage.extend(0..12);
+        for &id in [0, 3, 4, 8, 11].iter() {
+            storage.remove(id);
+        }
+
+        let slices: Vec<_> = storage.live_slices().collect();
+        assert_eq!(slices, vec![(1, &[1, 2][..]), (5, &[5, 6, 7][..]), (9, &[9, 10][..])]);
+
+        storage.insert_with_id(11, 11);
+        storage.insert_with_id(0, 0);
+        let slices: Vec<_> = storage.live_slices().collect();
+        assert_eq!(slices, vec![(0, &[0, 1, 2][..]), (5, &[5, 6, 7][..]), (9, &[9, 10, 11][..])]);
+
+        let mut storage = CacheStorage::with_capacity(8);
+        storage.extend(0..3);
+        assert_eq!(storage.live_slices().collect::<Vec<_>>(), vec![(0, &[0, 1, 2][..])]);
+    }
+
+    #[test]
+    fn test_cache_storage_reset() {
+        let mut storage = CacheStorage::new();
+        storage.extend(0..100);
+        for id in (0..100).step_by(3) {
+            storage.remove(id);
+        }
+        let stats = storage.stats();
+
+        storage.reset();
+        let reset_stats = storage.stats();
+        assert_eq!((reset_stats.live, reset_stats.total, reset_stats.free), (0, 0, 0));
+        assert_eq!(reset_stats.data_capacity, stats.data_capacity);
+        assert_eq!(reset_stats.free_list_capacity, stats.free_list_capacity);
+        assert_eq!(storage.iter_occupied().count(), 0);
+
+        assert_eq!(storage.insert(7).index(), 0);
+        assert_eq!(storage.stats().data_capacity, stats.data_capacity);
+    }
+
+    #[test]
+    fn test_cache_storage_classify() {
+        let mut storage = CacheStorage::new();
+        assert_eq!(storage.classify(0), IdState::NeverIssued);
+
+        storage.extend(0..4);
+        storage.remove(2);
+        assert_eq!(storage.classify(0), IdState::Live);
+        assert_eq!(storage.classify(2), IdState::Freed);
+        assert_eq!(storage.classify(4), IdState::NeverIssued);
+
+        storage.set_growth(Growth::Fixed(4));
+        storage.insert(2);
+        storage.insert(4);
+        assert_eq!(storage.classify(2), IdState::Live);
+        assert_eq!(storage.classify(4), IdState::Live);
+        assert_eq!(storage.classify(5), IdState::Freed);
+        assert_eq!(storage.classify(8), IdState::NeverIssued);
+
+        storage.insert(5);
+        assert_eq!(storage.classify(5), IdState::Live);
+    }
+
+    #[test]
+    fn test_cache_storage_warm() {
+        let mut storage = CacheStorage::new();
+        storage.warm(100);
+        assert_eq!(storage.stats().total, 100);
+        assert_eq!(storage.stats().free, 100);
+        assert!((0..100).all(|id| storage.classify(id) == IdState::Freed));
+
+        let capacity = storage.data.capacity();
+        let ptr = storage.data.as_ptr();
+        for i in 0..100 {
+            assert_eq!(storage.insert(i).index(), i);
+        }
+        assert_eq!(storage.data.capacity(), capacity);
+        assert_eq!(storage.data.as_ptr(), ptr);
+
+        storage.remove(7);
+        storage.warm(102);
+        assert_eq!(storage.insert(0).index(), 7);
+        assert_eq!(storage.insert(0).index(), 100);
+        assert_eq!(storage.insert(0).index(), 101);
+        assert_eq!(*storage.get(99), 99);
+        assert!(storage.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn test_cache_storage_modify_marks_dirty() {
+        let mut storage = CacheStorage::new();
+        storage.extend(vec![1, 2, 3]);
+
+        assert_eq!(*storage.get(1), 2);
+        assert_eq!(storage.get_checked(2), Some(&3));
+        assert!((0..3).all(|id| !storage.is_dirty(id)));
+
+        let old = storage.modify(1, |obj| mem::replace(obj, 20));
+        assert_eq!(old, 2);
+        assert_eq!(*storage.get(1), 20);
+        assert!(storage.is_dirty(1));
+        assert!(!storage.is_dirty(0) && !storage.is_dirty(2));
+
+        storage.clear_dirty(1);
+        assert!(!storage.is_dirty(1));
+
+        // The flag follows the element and doesn't outlive it
+        storage.modify(2, |obj| *obj = 30);
+        storage.modify(0, |obj| *obj = 10);
+        storage.remove(0);
+        assert!(!storage.is_dirty(0));
+        let remap = storage.compact_perm();
+        assert!(storage.is_dirty(to_id(remap[2])));
+        assert!(!storage.is_dirty(to_id(remap[1])));
+
+        // The flags are part of the saved state
+        let state = storage.save_state();
+        storage.clear_dirty(to_id(remap[2]));
+        storage.modify(to_id(remap[1]), |obj| *obj = 40);
+        storage.restore_state(state);
+        assert!(storage.is_dirty(to_id(remap[2])));
+        assert!(!storage.is_dirty(to_id(remap[1])));
+    }
+
+    #[test]
+    fn test_cache_storage_live_ids_with_ids_issued_ahead() {
+        let mut storage = CacheStorage::with_capacity(8);
+        assert_eq!(storage.first_live_id(), None);
+        assert_eq!(storage.max_live_id(), None);
+
+        storage.extend(0..5);
+        storage.remove(0);
+        storage.remove(4);
+        assert_eq!(storage.first_live_id(), Some(1));
+        assert_eq!(storage.max_live_id(), Some(3));
+        assert_eq!(storage.first(), Some((1, &1)));
+        assert_eq!(storage.last(), Some((3, &3)));
+
+        assert_eq!(storage.invalid_ids(vec![0, 1, 4, 5, 8]), vec![0, 4, 5, 8]);
+        assert_eq!(storage.get_batch(vec![0, 3, 6]).collect::<Vec<_>>(), vec![None, Some(&3), None]);
+    }
+
+    #[test]
+    fn test_cache_storage_flags_cleared_on_release() {
+        let mut storage = CacheStorage::new();
+        storage.extend(0..6);
+        storage.modify(1, |obj| *obj += 10);
+        storage.modify(2, |obj| *obj += 10);
+        let reserved = storage.reserve_id();
+
+        storage.rebuild_free_list(|id, &obj| obj < 10 && id != reserved);
+        assert!(!storage.is_live(1) && !storage.is_dirty(1));
+        assert!(!storage.is_live(2) && !storage.is_dirty(2));
+
+        // The stale value of the freed reservation is readable like any other
+        assert!(!storage.is_live(reserved));
+        assert_eq!(*storage.get(reserved), 0);
+
+        storage.modify(5, |obj| *obj += 1);
+        storage.resize_with(5, |_| 0);
+        assert!(!storage.is_dirty(5));
+
+        let mut storage = CacheStorage::new();
+        storage.extend(0..3);
+        storage.modify(0, |obj| *obj += 1);
+        let reserved = storage.reserve_id();
+        unsafe { storage.remove_chunk(vec![0, reserved]) }
+        assert!(!storage.is_dirty(0));
+
+        // Reading the reused id doesn't trip the pending `fill` check
+        assert_eq!(storage.insert(7).id(), reserved);
+        assert_eq!(*storage.get(reserved), 7);
+        assert_eq!(storage.insert(8).index(), 0);
+        assert!(!storage.is_dirty(0));
+    }
+
+    #[test]
+    fn test_cache_storage_backfilled_gaps_not_initialized() {
+        let mut storage = CacheStorage::new();
+        storage.extend_with_ids(vec![(0, 10), (3, 13)]);
+        assert!(storage.is_initialized(0) && storage.is_initialized(3));
+        assert!(!storage.is_initialized(1) && !storage.is_initialized(2));
+
+        // A gap made live by rebuilding the free list is still rejected
+        storage.rebuild_free_list(|_, _| true);
+        assert!(storage.is_live(1));
+        assert_eq!(storage.get_checked(1), None);
+
+        storage.insert_with_id(1, 11);
+        assert!(storage.is_initialized(1));
+        assert_eq!(storage.get_checked(1), Some(&11));
+        assert!(!storage.is_initialized(2));
+
+        let mut storage = CacheStorage::from_sparse(vec![Some(1), None, Some(3)]);
+        assert!(!storage.is_initialized(1));
+        assert_eq!(storage.insert(2).index(), 1);
+        assert!(storage.is_initialized(1));
+
+        let mut storage = CacheStorage::<u32>::new();
+        storage.warm(4);
+        assert!((0..4).all(|id| !storage.is_initialized(id)));
+        storage.insert(0);
+        assert!(storage.is_initialized(0) && !storage.is_initialized(1));
+
+        let state = storage.save_state();
+        storage.insert(1);
+        storage.restore_state(state);
+        assert!(!storage.is_initialized(1));
     }
 }
@@ -0,0 +1,74 @@
+use {
+    super::Id,
+    std::{error::Error, fmt, ops::Range},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FreeError {
+    OutOfRange(Id),
+    AlreadyFree(Id),
+}
+
+impl fmt::Display for FreeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::OutOfRange(id) => write!(f, "id {} is out of valid range", id),
+            Self::AlreadyFree(id) => write!(f, "id {} is already free", id),
+        }
+    }
+}
+
+impl Error for FreeError {}
+
+/// Returned by `CacheStorage::insert_bounded` when all issued ids are taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapacityError {
+    pub capacity: usize,
+}
+
+impl fmt::Display for CapacityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "storage is full, capacity is {}", self.capacity)
+    }
+}
+
+impl Error for CapacityError {}
+
+/// Returned by `CacheStorage::try_get_disjoint_mut`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisjointError {
+    Duplicate(Id),
+    Invalid(Id),
+}
+
+impl fmt::Display for DisjointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Duplicate(id) => write!(f, "id {} is requested more than once", id),
+            Self::Invalid(id) => write!(f, "id {} is out of range or freed", id),
+        }
+    }
+}
+
+impl Error for DisjointError {}
+
+/// Returned by `CacheStorage::try_insert_with_id` for an id outside of `valid_range`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InsertError {
+    pub id: Id,
+    pub valid_range: Range<Id>,
+}
+
+impl fmt::Display for InsertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "id {} is out of valid range {}..{}",
+            self.id,
+            self.valid_range.start,
+            self.valid_range.end
+        )
+    }
+}
+
+impl Error for InsertError {}
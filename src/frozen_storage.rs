@@ -0,0 +1,62 @@
+use super::{Id, index, enumerate_ids};
+
+/// Immutable compact storage produced by `CacheStorage::freeze`.
+/// All ids are dense: `0..len()`.
+#[derive(Debug, Clone)]
+pub struct FrozenStorage<T> {
+    data: Box<[T]>,
+}
+
+impl<T> FrozenStorage<T> {
+    pub(crate) fn from_boxed_slice(data: Box<[T]>) -> Self {
+        Self { data }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn get(&self, id: Id) -> &T {
+        &self.data[index(id)]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Id, &T)> {
+        enumerate_ids(self.data.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CacheStorage;
+
+    #[test]
+    fn test_freeze() {
+        let mut storage = CacheStorage::new();
+        for i in 0..6 {
+            storage.insert(i * 10);
+        }
+
+        storage.remove(1);
+        storage.remove(4);
+
+        let (frozen, remap) = storage.freeze();
+        assert_eq!(frozen.len(), 4);
+        assert!(!frozen.is_empty());
+        assert_eq!(
+            frozen.iter().map(|(_, &v)| v).collect::<Vec<_>>(),
+            vec![0, 20, 30, 50]
+        );
+
+        assert_eq!(remap.len(), 4);
+        for (old_id, new_id) in [(0, 0), (2, 1), (3, 2), (5, 3)].iter() {
+            assert_eq!(remap[old_id], *new_id);
+            assert_eq!(*frozen.get(*new_id), old_id * 10);
+        }
+        assert!(!remap.contains_key(&1));
+        assert!(!remap.contains_key(&4));
+    }
+}
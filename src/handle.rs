@@ -0,0 +1,91 @@
+use {
+    super::{Id, index},
+    std::sync::atomic::{AtomicU64, Ordering},
+};
+
+static NEXT_OWNER: AtomicU64 = AtomicU64::new(0);
+
+/// Returns a tag unique to the storage being created.
+pub(crate) fn new_owner() -> u64 {
+    NEXT_OWNER.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Id of an element inserted by `CacheStorage::insert`.
+/// It can't be constructed from a raw integer, it can't be copied
+/// and it is consumed by `CacheStorage::remove`, so the same handle can't be removed twice.
+/// Passing it to another storage panics.
+///
+/// # Note
+/// A raw id of the same element, like the one from `index`,
+/// is not tracked, so removing by it doesn't invalidate the handle.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct Handle {
+    id: Id,
+    owner: u64,
+}
+
+impl Handle {
+    pub(crate) fn new(id: Id, owner: u64) -> Self {
+        Self {
+            id,
+            owner,
+        }
+    }
+
+    pub(crate) fn id(&self) -> Id {
+        self.id
+    }
+
+    pub fn index(&self) -> usize {
+        index(self.id)
+    }
+
+    fn id_in(&self, owner: u64) -> Id {
+        assert_eq!(self.owner, owner, "handle {} belongs to another storage", self.id);
+        self.id()
+    }
+}
+
+mod private {
+    pub trait Sealed {}
+
+    impl Sealed for super::Id {}
+    impl Sealed for super::Handle {}
+    impl Sealed for &super::Handle {}
+}
+
+/// Element key accepted by `CacheStorage::get` and `get_mut`: a raw `Id` or a borrowed `Handle`.
+pub trait Key: private::Sealed {
+    #[doc(hidden)]
+    fn id_in(self, owner: u64) -> Id;
+}
+
+impl Key for Id {
+    fn id_in(self, _: u64) -> Id {
+        self
+    }
+}
+
+impl Key for &Handle {
+    fn id_in(self, owner: u64) -> Id {
+        Handle::id_in(self, owner)
+    }
+}
+
+/// Element key accepted by `CacheStorage::remove`: a raw `Id` or a `Handle`, which is consumed.
+pub trait OwnedKey: private::Sealed {
+    #[doc(hidden)]
+    fn into_id_in(self, owner: u64) -> Id;
+}
+
+impl OwnedKey for Id {
+    fn into_id_in(self, _: u64) -> Id {
+        self
+    }
+}
+
+impl OwnedKey for Handle {
+    fn into_id_in(self, owner: u64) -> Id {
+        self.id_in(owner)
+    }
+}
@@ -1,54 +1,271 @@
-use super::Id;
+use {
+    super::{index, to_id, FreeError, Id},
+    std::{
+        collections::{HashSet, TryReserveError, VecDeque},
+        mem,
+    },
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditOp {
+    Acquire,
+    Release,
+}
+
+/// Where `acquire_id_tracked` took the id from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reused {
+    FromFreeList,
+    Fresh,
+}
+
+/// Which free id `try_acquire_id` takes.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ReusePolicy {
+    /// The most recently released id.
+    #[default]
+    Lifo,
+
+    /// The least recently released id, so a released id is reused as late as possible.
+    Fifo,
+
+    /// Like `Lifo`, but while the ratio of free ids to `top_id` exceeds `threshold`
+    /// the lowest free id is taken, so the acquired ids get dense again.
+    Adaptive { threshold: f64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditEvent {
+    pub op: AuditOp,
+    pub id: Id,
+    pub seq: u64,
+}
+
+#[derive(Debug, Clone)]
 pub struct IdCache {
+    start_id: usize,
     top_id: usize,
-    pub(crate) free_ids: Vec<Id>,
+    pub(crate) free_ids: VecDeque<Id>,
+    audit: Option<Vec<AuditEvent>>,
+    total_acquired: u64,
+    reuse_policy: ReusePolicy,
 }
 
 impl IdCache {
     pub fn new() -> Self {
+        Self::new_starting_at(0)
+    }
+
+    /// Creates a cache that reuses the released ids oldest first, see `ReusePolicy::Fifo`.
+    pub fn fifo() -> Self {
+        let mut cache = Self::new();
+        cache.set_reuse_policy(ReusePolicy::Fifo);
+
+        cache
+    }
+
+    /// Creates a cache that never issues ids `0..start`.
+    pub fn new_starting_at(start: Id) -> Self {
         Self {
-            top_id: 0,
+            start_id: index(start),
+            top_id: index(start),
             free_ids: Default::default(),
+            audit: None,
+            total_acquired: 0,
+            reuse_policy: ReusePolicy::default(),
         }
     }
 
+    /// Pre-issues ids `0..capacity` as free ids, so `top_id` is `capacity`
+    /// and they are acquired in ascending order before any new id.
+    /// See `with_free_list_capacity` to only allocate the free list.
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
-            top_id: capacity.into(),
-            free_ids: (0..capacity).rev().collect(),
+            start_id: 0,
+            top_id: capacity,
+            free_ids: (0..capacity).rev().map(to_id).collect(),
+            audit: None,
+            total_acquired: 0,
+            reuse_policy: ReusePolicy::default(),
+        }
+    }
+
+    /// Allocates room for `capacity` free ids without issuing any.
+    pub fn with_free_list_capacity(capacity: usize) -> Self {
+        Self {
+            start_id: 0,
+            top_id: 0,
+            free_ids: VecDeque::with_capacity(capacity),
+            audit: None,
+            total_acquired: 0,
+            reuse_policy: ReusePolicy::default(),
         }
     }
 
+    /// Same as `with_capacity`, but returns an error instead of aborting
+    /// if the free list can't be allocated.
+    pub fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError> {
+        let mut free_ids = VecDeque::new();
+        free_ids.try_reserve_exact(capacity)?;
+        free_ids.extend((0..capacity).rev().map(to_id));
+
+        Ok(Self {
+            start_id: 0,
+            top_id: capacity,
+            free_ids,
+            audit: None,
+            total_acquired: 0,
+            reuse_policy: ReusePolicy::default(),
+        })
+    }
+
+    /// Issues `additional` new free ids above `top_id`.
+    /// They are acquired after the ids that are free already, in ascending order.
+    pub fn grow(&mut self, additional: usize) {
+        let new_top_id = self.top_id + additional;
+        self.push_reused_last((self.top_id..new_top_id).map(to_id));
+        self.top_id = new_top_id;
+    }
+
+    /// Appends the ids of `other` shifted by `top_id` of `self`.
+    /// The free ids of `other` (and the ids below its starting id) become free in `self`
+    /// and are acquired after the ids that are free already, like in `grow`.
+    /// Returns the offset that translates ids of `other` to ids of `self`.
+    ///
+    /// # Note
+    /// The audit log of `other` is discarded.
+    pub fn append(&mut self, other: IdCache) -> usize {
+        let offset = self.top_id;
+
+        let mut other_free_ids = Vec::from(other.free_ids);
+        if other.reuse_policy != ReusePolicy::Fifo {
+            other_free_ids.reverse();
+        }
+
+        let other_free_ids = (0..other.start_id)
+            .map(to_id)
+            .chain(other_free_ids)
+            .map(|id| to_id(index(id) + offset));
+        self.push_reused_last(other_free_ids);
+        self.top_id += other.top_id;
+
+        offset
+    }
+
+    /// Creates a cache where ids `0..top_id` are already acquired.
+    pub(crate) fn with_top_id(top_id: usize) -> Self {
+        Self {
+            start_id: 0,
+            top_id,
+            free_ids: Default::default(),
+            audit: None,
+            total_acquired: 0,
+            reuse_policy: ReusePolicy::default(),
+        }
+    }
+
+    /// # Panics
+    /// If the id space is exhausted, see `checked_acquire_id`.
     pub fn acquire_id(&mut self) -> Id {
-        match self.try_acquire_id() {
-            Some(id) => id,
-            None => {
-                let old_top_id = self.top_id;
-                self.top_id += 1;
+        self.checked_acquire_id().expect("id space is exhausted")
+    }
 
-                old_top_id
-            },
+    /// The same as `acquire_id`, but returns `None` instead of overflowing the id type.
+    /// `Id::MAX` is never issued.
+    pub fn checked_acquire_id(&mut self) -> Option<Id> {
+        if let Some(id) = self.try_acquire_id() {
+            return Some(id);
         }
+
+        if self.top_id >= index(Id::MAX) {
+            return None;
+        }
+
+        let id = to_id(self.top_id);
+        self.top_id += 1;
+
+        record(&mut self.audit, AuditOp::Acquire, id);
+        self.total_acquired += 1;
+
+        Some(id)
+    }
+
+    /// The same as `acquire_id`, but also reports whether the id was reused.
+    pub fn acquire_id_tracked(&mut self) -> (Id, Reused) {
+        let reused = if self.free_ids.is_empty() {
+            Reused::Fresh
+        } else {
+            Reused::FromFreeList
+        };
+
+        (self.acquire_id(), reused)
     }
 
     pub fn try_acquire_id(&mut self) -> Option<Id> {
-        self.free_ids.pop()
+        if self.free_ids.is_empty() {
+            return None;
+        }
+
+        let id = match self.reuse_policy {
+            ReusePolicy::Fifo => self.free_ids.pop_front()?,
+            _ if self.is_fragmented() => {
+                let position = self.free_ids
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|&(_, &id)| id)
+                    .map(|(position, _)| position)?;
+
+                self.free_ids.remove(position)?
+            },
+            _ => self.free_ids.pop_back()?,
+        };
+
+        record(&mut self.audit, AuditOp::Acquire, id);
+        self.total_acquired += 1;
+
+        Some(id)
+    }
+
+    /// Marks exactly `id` as acquired.
+    /// If `id >= self.top_id`, the ids in between become free.
+    /// Returns `false` if `id` is already acquired.
+    pub fn acquire_exact_id(&mut self, id: Id) -> bool {
+        if index(id) >= self.top_id {
+            self.push_released((self.top_id..index(id)).map(to_id));
+            self.top_id = index(id) + 1;
+        } else if let Some(position) = self.free_ids.iter().position(|&free_id| free_id == id) {
+            self.free_ids.remove(position);
+        } else {
+            return false;
+        }
+
+        record(&mut self.audit, AuditOp::Acquire, id);
+        self.total_acquired += 1;
+
+        true
     }
 
     /// # Panics
     /// [DEBUG CFG]
     /// * If `id >= self.top_id`
+    /// * If `id` is below the starting id
     /// * If `id` was already released
     pub fn release_id(&mut self, id: Id) {
         debug_assert!(
-            self.free_ids.iter().find(|&&free_id| free_id == id).is_none(),
-            "id double release"
+            !self.free_ids.contains(&id),
+            "id {} double release",
+            id
+        );
+        debug_assert!(
+            self.start_id <= index(id) && index(id) < self.top_id,
+            "id {} is out of range {}..{}",
+            id,
+            self.start_id,
+            self.top_id
         );
-        debug_assert!(id < self.top_id);
 
-        self.free_ids.push(id);
+        self.free_ids.push_back(id);
+        record(&mut self.audit, AuditOp::Release, id);
     }
 
     /// # Safety
@@ -61,26 +278,318 @@ impl IdCache {
     pub unsafe fn release_ids<I: IntoIterator<Item = Id>>(&mut self, ids: I) {
         let ids = ids.into_iter();
 
-        let top_id = self.top_id;
+        let (start_id, top_id) = (self.start_id, self.top_id);
+        let audit = &mut self.audit;
         self.free_ids.extend(ids.inspect(|&id| {
-            debug_assert!(id < top_id);
+            debug_assert!(
+                start_id <= index(id) && index(id) < top_id,
+                "id {} is out of range {}..{}",
+                id,
+                start_id,
+                top_id
+            );
+            record(audit, AuditOp::Release, id);
         }));
     }
 
+    /// Safe alternative to `release_ids` for strictly increasing `ids`.
+    ///
+    /// # Panics
+    /// * If `ids` are not strictly increasing
+    /// * If some `id` from the `ids` is out of the acquired range
+    /// * [DEBUG CFG] If some `id` was already released
+    pub fn release_ids_sorted_unique<I: IntoIterator<Item = Id>>(&mut self, ids: I) {
+        let mut prev_id = None;
+        for id in ids {
+            assert!(
+                prev_id.map_or(true, |prev_id| prev_id < id),
+                "ids are not strictly increasing: {} after {}",
+                id,
+                prev_id.unwrap_or_default()
+            );
+            assert!(
+                self.start_id <= index(id) && index(id) < self.top_id,
+                "id {} is out of range {}..{}",
+                id,
+                self.start_id,
+                self.top_id
+            );
+            prev_id = Some(id);
+
+            self.release_id(id);
+        }
+    }
+
+    /// Moves up to `count` free ids, the ones `self` would reuse first, to `other`.
+    /// They are released in `other` in the reversed order,
+    /// so unless `other` is `Fifo` they are reused first there in the same order.
+    /// Returns the number of moved ids.
+    ///
+    /// Both caches must manage the same id space:
+    /// the moved ids must be acquired in `other`, so `other` can release them.
+    ///
+    /// # Panics
+    /// [DEBUG CFG]
+    /// The same as `release_id` of `other` for every moved id.
+    pub fn transfer_free_to(&mut self, other: &mut IdCache, count: usize) -> usize {
+        let count = count.min(self.free_ids.len());
+
+        if self.reuse_policy == ReusePolicy::Fifo {
+            for id in self.free_ids.drain(..count).rev() {
+                other.release_id(id);
+            }
+        } else {
+            let start = self.free_ids.len() - count;
+            for id in self.free_ids.drain(start..) {
+                other.release_id(id);
+            }
+        }
+
+        count
+    }
+
+    /// Takes the free list out, leaving the cache without free ids.
+    /// The ids are in the reversed order of acquiring.
+    pub fn take_free_ids(&mut self) -> Vec<Id> {
+        let mut free_ids = Vec::from(mem::take(&mut self.free_ids));
+        if self.reuse_policy == ReusePolicy::Fifo {
+            free_ids.reverse();
+        }
+
+        free_ids
+    }
+
+    /// Replaces the free list with `free_ids`, acquired from the end.
+    /// The free list is not changed if some id is out of the issued range or repeated.
+    pub fn set_free_ids(&mut self, free_ids: Vec<Id>) -> Result<(), FreeError> {
+        let mut seen = HashSet::with_capacity(free_ids.len());
+        for &id in free_ids.iter() {
+            if index(id) < self.start_id || index(id) >= self.top_id {
+                return Err(FreeError::OutOfRange(id));
+            }
+
+            if !seen.insert(id) {
+                return Err(FreeError::AlreadyFree(id));
+            }
+        }
+
+        self.free_ids = free_ids.into();
+        if self.reuse_policy == ReusePolicy::Fifo {
+            self.free_ids.make_contiguous().reverse();
+        }
+
+        Ok(())
+    }
+
+    /// Releases every id in `checkpoint_top_id..top_id` that is not free yet.
+    /// Capture `top_id` at the start of a frame to free everything issued
+    /// during the frame in one shot.
+    /// The freed ids are reused starting from the lowest one.
+    pub fn release_since(&mut self, checkpoint_top_id: usize) {
+        let since = checkpoint_top_id.max(self.start_id);
+        if since >= self.top_id {
+            return;
+        }
+
+        let already_free: HashSet<Id> = self.free_ids
+            .iter()
+            .copied()
+            .filter(|&id| index(id) >= since)
+            .collect();
+
+        let released: Vec<Id> = (since..self.top_id)
+            .map(to_id)
+            .filter(|id| !already_free.contains(id))
+            .collect();
+
+        self.push_released(released.iter().copied());
+        for &id in released.iter() {
+            record(&mut self.audit, AuditOp::Release, id);
+        }
+    }
+
+    /// Sorts the free ids, so they are acquired lowest first.
+    /// The set of free ids doesn't change.
+    pub fn sort_free_list(&mut self) {
+        let free_ids = self.free_ids.make_contiguous();
+        if self.reuse_policy == ReusePolicy::Fifo {
+            free_ids.sort_unstable();
+        } else {
+            free_ids.sort_unstable_by(|a, b| b.cmp(a));
+        }
+    }
+
+    /// The ids that are free already keep the order they are acquired in,
+    /// the new policy applies to the ids released afterwards.
+    pub fn set_reuse_policy(&mut self, reuse_policy: ReusePolicy) {
+        let was_fifo = self.reuse_policy == ReusePolicy::Fifo;
+        if was_fifo != (reuse_policy == ReusePolicy::Fifo) {
+            self.free_ids.make_contiguous().reverse();
+        }
+
+        self.reuse_policy = reuse_policy;
+    }
+
+    pub fn reuse_policy(&self) -> ReusePolicy {
+        self.reuse_policy
+    }
+
+    /// Adds `ids` to the free list the way `release_id` does,
+    /// so among themselves they are acquired in the order of `ids`.
+    fn push_released<I: DoubleEndedIterator<Item = Id>>(&mut self, ids: I) {
+        if self.reuse_policy == ReusePolicy::Fifo {
+            self.free_ids.extend(ids);
+        } else {
+            self.free_ids.extend(ids.rev());
+        }
+    }
+
+    /// Adds `ids` to the free list, so they are acquired after every id that is free already,
+    /// in the order of `ids`.
+    fn push_reused_last<I: Iterator<Item = Id>>(&mut self, ids: I) {
+        if self.reuse_policy == ReusePolicy::Fifo {
+            self.free_ids.extend(ids);
+        } else {
+            for id in ids {
+                self.free_ids.push_front(id);
+            }
+        }
+    }
+
+    fn is_fragmented(&self) -> bool {
+        match self.reuse_policy {
+            ReusePolicy::Lifo | ReusePolicy::Fifo => false,
+            ReusePolicy::Adaptive { threshold } => self.top_id > 0
+                && self.free_ids.len() as f64 / self.top_id as f64 > threshold,
+        }
+    }
+
+    /// Starts recording every acquire and release, see `audit_log`.
+    pub fn enable_audit(&mut self) {
+        self.audit.get_or_insert_with(Vec::new);
+    }
+
+    pub fn disable_audit(&mut self) {
+        self.audit = None;
+    }
+
+    /// Returns the recorded events, empty if the audit is disabled.
+    pub fn audit_log(&self) -> &[AuditEvent] {
+        self.audit.as_deref().unwrap_or_default()
+    }
+
+    /// Makes the cache issue ids from the starting id again.
+    /// The free list keeps its capacity.
+    /// The counters like `total_acquired` are kept, see `reset_counters`.
     pub fn reset(&mut self) {
-        self.top_id = 0;
+        self.top_id = self.start_id;
         self.free_ids.clear();
     }
 
+    /// The same as `reset`, but the ids up to `top_id` stay acquired.
+    pub(crate) fn reset_to_top_id(&mut self, top_id: usize) {
+        self.top_id = top_id;
+        self.free_ids.clear();
+    }
+
+    /// Returns the ids released after `free_ids_num` was `marker`.
+    /// The free list is a ring buffer, so they come in two slices, the second one continues the first.
+    ///
+    /// # Note
+    /// Acquires and `grow` shift the free list,
+    /// so after one following the snapshot the result is not reliable anymore.
+    pub fn free_ids_since(&self, marker: usize) -> (&[Id], &[Id]) {
+        let (front, back) = self.free_ids.as_slices();
+        if marker <= front.len() {
+            (&front[marker..], back)
+        } else {
+            (&[], &back[(marker - front.len()).min(back.len())..])
+        }
+    }
+
+    /// Returns the number of acquired ids, including the reused ones,
+    /// since the creation or `reset_counters`.
+    pub fn total_acquired(&self) -> u64 {
+        self.total_acquired
+    }
+
+    pub fn reset_counters(&mut self) {
+        self.total_acquired = 0;
+    }
+
     pub fn free_ids_num(&self) -> usize {
         self.free_ids.len()
     }
+
+    pub fn top_id(&self) -> usize {
+        self.top_id
+    }
+
+    /// Verifies that every free id is in the issued range, occurs once,
+    /// and that there are not more free ids than issued ones.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let issued_num = self.top_id - self.start_id;
+        if self.free_ids.len() > issued_num {
+            return Err(format!(
+                "{} free ids, but only {} ids were issued",
+                self.free_ids.len(),
+                issued_num
+            ));
+        }
+
+        let mut seen = HashSet::with_capacity(self.free_ids.len());
+        for &id in &self.free_ids {
+            if index(id) < self.start_id || index(id) >= self.top_id {
+                return Err(format!(
+                    "free id {} is out of range {}..{}",
+                    id,
+                    self.start_id,
+                    self.top_id
+                ));
+            }
+
+            if !seen.insert(id) {
+                return Err(format!("free id {} is duplicated", id));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lowers `top_id` while the id right below it is free,
+    /// removing such ids from the free list.
+    pub fn trim_free_tail(&mut self) {
+        while self.top_id > self.start_id {
+            let last_id = self.top_id - 1;
+            match self.free_ids.iter().position(|&free_id| index(free_id) == last_id) {
+                Some(position) => {
+                    self.free_ids.remove(position);
+                    self.top_id = last_id;
+                },
+                None => break,
+            }
+        }
+    }
+}
+
+fn record(audit: &mut Option<Vec<AuditEvent>>, op: AuditOp, id: Id) {
+    if let Some(log) = audit {
+        let seq = log.len() as u64;
+        log.push(AuditEvent { op, id, seq });
+    }
+}
+
+impl Default for IdCache {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::vec_init_then_push)]
 mod tests {
     use {
-        crate::IdCache,
+        crate::{FreeError, Id, IdCache, AuditEvent, AuditOp, Reused, ReusePolicy, index, to_id},
         std::{collections::HashSet, iter::FromIterator},
     };
 
@@ -173,7 +682,7 @@ mod tests {
             cache.acquire_id();
             assert_eq!(
                 cache.free_ids,
-                (i..capacity).rev().collect::<Vec<_>>()
+                (i..capacity).rev().map(to_id).collect::<Vec<_>>()
             );
             assert_eq!(cache.free_ids_num(), capacity - i);
         }
@@ -183,7 +692,7 @@ mod tests {
         assert_eq!(cache.free_ids_num(), 0);
 
         let new_id = cache.acquire_id();
-        assert_eq!(new_id, capacity);
+        assert_eq!(index(new_id), capacity);
         assert_eq!(cache.top_id, capacity + 1);
         assert_eq!(cache.free_ids_num(), 0);
 
@@ -193,6 +702,192 @@ mod tests {
         assert_eq!(cache.free_ids_num(), 1);
     }
 
+    #[test]
+    fn test_acquire_exact_id() {
+        let mut cache = IdCache::new();
+        assert!(cache.acquire_exact_id(3));
+        assert_eq!(cache.top_id(), 4);
+        assert_eq!(cache.free_ids, vec![2, 1, 0]);
+
+        assert!(cache.acquire_exact_id(1));
+        assert_eq!(cache.free_ids, vec![2, 0]);
+        assert!(!cache.acquire_exact_id(1));
+        assert!(!cache.acquire_exact_id(3));
+
+        assert_eq!(cache.acquire_id(), 0);
+        assert_eq!(cache.acquire_id(), 2);
+        assert_eq!(cache.acquire_id(), 4);
+    }
+
+    #[test]
+    fn test_trim_free_tail() {
+        let mut cache = IdCache::new();
+        for _ in 0..5 {
+            cache.acquire_id();
+        }
+
+        cache.release_id(1);
+        cache.release_id(3);
+        cache.trim_free_tail();
+        assert_eq!(cache.top_id(), 5);
+        assert_eq!(cache.free_ids, vec![1, 3]);
+
+        cache.release_id(4);
+        cache.trim_free_tail();
+        assert_eq!(cache.top_id(), 3);
+        assert_eq!(cache.free_ids, vec![1]);
+
+        cache.release_id(2);
+        cache.trim_free_tail();
+        assert_eq!(cache.top_id(), 1);
+        assert!(cache.free_ids.is_empty());
+
+        assert_eq!(cache.acquire_id(), 1);
+    }
+
+    #[test]
+    fn test_id_cache_new_starting_at() {
+        let mut cache = IdCache::new_starting_at(1);
+        assert_eq!(cache.top_id(), 1);
+        assert!(cache.free_ids.is_empty());
+
+        assert_eq!(cache.acquire_id(), 1);
+        assert_eq!(cache.acquire_id(), 2);
+
+        cache.release_id(2);
+        cache.release_id(1);
+        cache.trim_free_tail();
+        assert_eq!(cache.top_id(), 1);
+
+        assert_eq!(cache.acquire_id(), 1);
+        cache.reset();
+        assert_eq!(cache.acquire_id(), 1);
+    }
+
+    #[test]
+    fn test_id_cache_try_with_capacity() {
+        let cache = IdCache::try_with_capacity(4).unwrap();
+        assert_eq!(cache.top_id(), 4);
+        assert_eq!(cache.free_ids, vec![3, 2, 1, 0]);
+
+        assert!(IdCache::try_with_capacity(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_id_cache_grow() {
+        let mut cache = IdCache::with_capacity(2);
+        cache.acquire_id();
+        cache.grow(3);
+        assert_eq!(cache.top_id(), 5);
+        assert_eq!(cache.free_ids, vec![4, 3, 2, 1]);
+
+        let ids: Vec<_> = (0..5).map(|_| cache.acquire_id()).collect();
+        assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "id 3 is out of range 0..2")]
+    fn test_release_id_out_of_range() {
+        let mut cache = IdCache::new();
+        cache.acquire_id();
+        cache.acquire_id();
+        cache.release_id(3);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "id 1 double release")]
+    fn test_release_id_twice() {
+        let mut cache = IdCache::new();
+        cache.acquire_id();
+        cache.acquire_id();
+        cache.release_id(1);
+        cache.release_id(1);
+    }
+
+    #[test]
+    fn test_release_ids_sorted_unique() {
+        let mut cache = IdCache::new();
+        for _ in 0..6 {
+            cache.acquire_id();
+        }
+
+        cache.release_ids_sorted_unique(vec![1, 3, 4]);
+        assert_eq!(cache.free_ids, vec![1, 3, 4]);
+    }
+
+    #[test]
+    #[should_panic(expected = "ids are not strictly increasing: 3 after 3")]
+    fn test_release_ids_sorted_unique_duplicate() {
+        let mut cache = IdCache::with_capacity(6);
+        cache.free_ids.clear();
+
+        cache.release_ids_sorted_unique(vec![1, 3, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "id 6 is out of range 0..6")]
+    fn test_release_ids_sorted_unique_out_of_range() {
+        let mut cache = IdCache::with_capacity(6);
+        cache.free_ids.clear();
+
+        cache.release_ids_sorted_unique(vec![5, 6]);
+    }
+
+    #[test]
+    fn test_id_cache_check_invariants() {
+        let mut cache = IdCache::with_capacity(4);
+        assert_eq!(cache.check_invariants(), Ok(()));
+
+        cache.acquire_id();
+        cache.acquire_id();
+        cache.release_id(1);
+        assert_eq!(cache.check_invariants(), Ok(()));
+
+        cache.free_ids.push_back(1);
+        assert_eq!(cache.check_invariants(), Err("free id 1 is duplicated".to_string()));
+
+        cache.free_ids.pop_back();
+        cache.free_ids.push_back(4);
+        assert_eq!(cache.check_invariants(), Err("free id 4 is out of range 0..4".to_string()));
+
+        cache.free_ids = vec![0, 1, 2, 3, 0].into();
+        assert_eq!(
+            cache.check_invariants(),
+            Err("5 free ids, but only 4 ids were issued".to_string())
+        );
+    }
+
+    #[test]
+    fn test_id_cache_audit() {
+        let mut cache = IdCache::new();
+        cache.acquire_id();
+        assert!(cache.audit_log().is_empty());
+
+        cache.enable_audit();
+        let id = cache.acquire_id();
+        cache.release_id(id);
+        cache.release_id(0);
+        cache.acquire_id();
+        unsafe { cache.release_ids(vec![0]) }
+
+        assert_eq!(
+            cache.audit_log(),
+            &[
+                AuditEvent { op: AuditOp::Acquire, id: 1, seq: 0 },
+                AuditEvent { op: AuditOp::Release, id: 1, seq: 1 },
+                AuditEvent { op: AuditOp::Release, id: 0, seq: 2 },
+                AuditEvent { op: AuditOp::Acquire, id: 0, seq: 3 },
+                AuditEvent { op: AuditOp::Release, id: 0, seq: 4 },
+            ]
+        );
+
+        cache.disable_audit();
+        cache.acquire_id();
+        assert!(cache.audit_log().is_empty());
+    }
+
     #[test]
     fn test_try_acquire_id() {
         let mut cache = IdCache::new();
@@ -205,4 +900,345 @@ mod tests {
         assert!(freed_id.is_some());
         assert_eq!(freed_id.unwrap(), src_id);
     }
+
+    #[test]
+    fn test_id_cache_release_since() {
+        let mut cache = IdCache::new();
+        for _ in 0..3 {
+            cache.acquire_id();
+        }
+
+        let checkpoint = cache.top_id();
+        for _ in 0..5 {
+            cache.acquire_id();
+        }
+        cache.release_id(5);
+
+        cache.release_since(checkpoint);
+        assert_eq!(cache.free_ids_num(), 5);
+        assert_eq!(cache.free_ids.iter().copied().collect::<HashSet<_>>(), (3..8).collect());
+        assert!(cache.check_invariants().is_ok());
+
+        assert_eq!(cache.acquire_id(), 3);
+
+        cache.release_since(cache.top_id());
+        cache.release_since(100);
+        assert_eq!(cache.free_ids_num(), 4);
+    }
+
+    #[test]
+    fn test_id_cache_acquire_id_tracked() {
+        let mut cache = IdCache::new();
+        assert_eq!(cache.acquire_id_tracked(), (0, Reused::Fresh));
+        assert_eq!(cache.acquire_id_tracked(), (1, Reused::Fresh));
+
+        cache.release_id(0);
+        assert_eq!(cache.acquire_id_tracked(), (0, Reused::FromFreeList));
+        assert_eq!(cache.acquire_id_tracked(), (2, Reused::Fresh));
+    }
+
+    #[test]
+    fn test_id_cache_sort_free_list() {
+        let mut cache = IdCache::new();
+        for _ in 0..8 {
+            cache.acquire_id();
+        }
+
+        for &id in [5, 1, 7, 3, 2].iter() {
+            cache.release_id(id);
+        }
+
+        cache.sort_free_list();
+        assert_eq!(cache.free_ids_num(), 5);
+
+        let acquired: Vec<_> = (0..5).map(|_| cache.acquire_id()).collect();
+        assert_eq!(acquired, vec![1, 2, 3, 5, 7]);
+        assert_eq!(cache.acquire_id(), 8);
+    }
+
+    #[test]
+    fn test_id_cache_with_free_list_capacity() {
+        let mut cache = IdCache::with_free_list_capacity(10);
+        assert!(cache.free_ids.capacity() >= 10);
+        assert_eq!(cache.free_ids_num(), 0);
+        assert_eq!(cache.top_id(), 0);
+        assert_eq!(cache.acquire_id(), 0);
+    }
+
+    #[test]
+    fn test_id_cache_append() {
+        let mut cache = IdCache::new();
+        for _ in 0..4 {
+            cache.acquire_id();
+        }
+        cache.release_id(1);
+
+        let mut other = IdCache::new_starting_at(1);
+        for _ in 0..3 {
+            other.acquire_id();
+        }
+
+        other.release_id(2);
+
+        assert_eq!(cache.append(other), 4);
+        assert_eq!(cache.top_id(), 8);
+        assert_eq!(cache.free_ids_num(), 3);
+        assert!(cache.check_invariants().is_ok());
+
+        let acquired: Vec<_> = (0..3).map(|_| cache.acquire_id()).collect();
+        assert_eq!(acquired, vec![1, 4, 6]);
+        assert_eq!(cache.acquire_id(), 8);
+    }
+
+    #[test]
+    fn test_id_cache_total_acquired() {
+        let mut cache = IdCache::new();
+        let id = cache.acquire_id();
+        cache.release_id(id);
+        cache.acquire_id();
+        cache.acquire_exact_id(5);
+        assert!(cache.try_acquire_id().is_some());
+        assert_eq!(cache.total_acquired(), 4);
+
+        cache.reset();
+        assert_eq!(cache.total_acquired(), 4);
+        cache.acquire_id();
+        assert_eq!(cache.total_acquired(), 5);
+
+        cache.reset_counters();
+        assert_eq!(cache.total_acquired(), 0);
+    }
+
+    #[test]
+    fn test_id_cache_checked_acquire_id() {
+        let mut cache = IdCache::new();
+        assert_eq!(cache.checked_acquire_id(), Some(0));
+
+        cache.top_id = index(Id::MAX) - 2;
+        assert_eq!(cache.checked_acquire_id(), Some(Id::MAX - 2));
+        assert_eq!(cache.checked_acquire_id(), Some(Id::MAX - 1));
+        assert_eq!(cache.checked_acquire_id(), None);
+        assert_eq!(cache.top_id(), index(Id::MAX));
+
+        cache.release_id(0);
+        assert_eq!(cache.checked_acquire_id(), Some(0));
+        assert_eq!(cache.checked_acquire_id(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "id space is exhausted")]
+    fn test_id_cache_acquire_id_exhausted() {
+        let mut cache = IdCache::new();
+        cache.top_id = index(Id::MAX);
+        cache.acquire_id();
+    }
+
+    #[test]
+    fn test_id_cache_transfer_free_to() {
+        let mut producer = IdCache::new();
+        let mut consumer = IdCache::new();
+        for _ in 0..5 {
+            producer.acquire_id();
+            consumer.acquire_id();
+        }
+
+        producer.release_ids_sorted_unique(vec![1, 2, 3]);
+        assert_eq!(producer.transfer_free_to(&mut consumer, 2), 2);
+        assert_eq!(producer.free_ids_num(), 1);
+        assert_eq!(consumer.free_ids_num(), 2);
+
+        assert_eq!(consumer.acquire_id(), 3);
+        assert_eq!(consumer.acquire_id(), 2);
+        assert_eq!(producer.acquire_id(), 1);
+        assert_eq!(producer.acquire_id(), 5);
+
+        assert_eq!(producer.transfer_free_to(&mut consumer, 10), 0);
+    }
+
+    #[test]
+    fn test_id_cache_free_ids_since() {
+        let mut cache = IdCache::new();
+        for _ in 0..6 {
+            cache.acquire_id();
+        }
+
+        cache.release_id(0);
+        let marker = cache.free_ids_num();
+        assert_eq!(cache.free_ids_since(marker), (&[] as &[Id], &[] as &[Id]));
+
+        cache.release_id(4);
+        cache.release_id(2);
+        cache.release_id(5);
+        let (front, back) = cache.free_ids_since(marker);
+        assert_eq!([front, back].concat(), vec![4, 2, 5]);
+        assert_eq!(cache.free_ids_since(100), (&[] as &[Id], &[] as &[Id]));
+
+        // The released ids wrap around the end of the ring buffer
+        let mut cache = IdCache::with_free_list_capacity(4);
+        cache.set_reuse_policy(ReusePolicy::Fifo);
+        for _ in 0..8 {
+            cache.acquire_id();
+        }
+
+        cache.release_ids_sorted_unique(0..4);
+        for _ in 0..3 {
+            cache.acquire_id();
+        }
+
+        let marker = cache.free_ids_num();
+        cache.release_ids_sorted_unique(4..7);
+        let (front, back) = cache.free_ids_since(marker);
+        assert_eq!([front, back].concat(), vec![4, 5, 6]);
+    }
+
+    #[test]
+    fn test_id_cache_take_set_free_ids() {
+        let mut cache = IdCache::new();
+        for _ in 0..6 {
+            cache.acquire_id();
+        }
+        for &id in [4, 1, 3, 0].iter() {
+            cache.release_id(id);
+        }
+
+        let mut free_ids = cache.take_free_ids();
+        assert_eq!(cache.free_ids_num(), 0);
+        assert_eq!(free_ids, vec![4, 1, 3, 0]);
+
+        free_ids.retain(|&id| id != 3);
+        free_ids.sort_unstable();
+        assert_eq!(cache.set_free_ids(vec![1, 6]), Err(FreeError::OutOfRange(6)));
+        assert_eq!(cache.set_free_ids(vec![1, 4, 1]), Err(FreeError::AlreadyFree(1)));
+        assert_eq!(cache.free_ids_num(), 0);
+
+        assert_eq!(cache.set_free_ids(free_ids), Ok(()));
+        let acquired: Vec<_> = (0..4).map(|_| cache.acquire_id()).collect();
+        assert_eq!(acquired, vec![4, 1, 0, 6]);
+    }
+
+    #[test]
+    fn test_adaptive_reuse_policy() {
+        let mut cache = IdCache::new();
+        for _ in 0..10 {
+            cache.acquire_id();
+        }
+
+        let mut lifo = cache.clone();
+        for &id in [1, 3, 5, 7, 2].iter() {
+            cache.release_id(id);
+            lifo.release_id(id);
+        }
+        assert_eq!(lifo.acquire_id(), 2);
+
+        cache.set_reuse_policy(ReusePolicy::Adaptive { threshold: 0.3 });
+        assert_eq!(cache.acquire_id(), 1);
+        assert_eq!(cache.acquire_id(), 2);
+
+        // 3 of 10 ids are free, which doesn't exceed the threshold
+        assert_eq!(cache.acquire_id(), 7);
+        assert_eq!(cache.acquire_id(), 5);
+        assert_eq!(cache.acquire_id(), 3);
+        assert_eq!(cache.acquire_id(), 10);
+        assert!(cache.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn test_id_cache_reset_keeps_capacity() {
+        let mut cache = IdCache::new();
+        for _ in 0..100 {
+            cache.acquire_id();
+        }
+        for id in 0..100 {
+            cache.release_id(id);
+        }
+        let capacity = cache.free_ids.capacity();
+
+        cache.reset();
+        assert_eq!((cache.top_id(), cache.free_ids_num()), (0, 0));
+        assert_eq!(cache.free_ids.capacity(), capacity);
+        assert_eq!(cache.acquire_id(), 0);
+    }
+
+    #[test]
+    fn test_lifo_and_fifo_reuse_order() {
+        let mut lifo = IdCache::new();
+        let mut fifo = IdCache::fifo();
+        for cache in [&mut lifo, &mut fifo].iter_mut() {
+            for _ in 0..10 {
+                cache.acquire_id();
+            }
+
+            cache.release_id(5);
+            cache.release_id(2);
+            cache.release_id(8);
+        }
+
+        assert_eq!([lifo.acquire_id(), lifo.acquire_id(), lifo.acquire_id()], [8, 2, 5]);
+        assert_eq!([fifo.acquire_id(), fifo.acquire_id(), fifo.acquire_id()], [5, 2, 8]);
+
+        fifo.release_id(2);
+        fifo.release_id(5);
+        assert_eq!(fifo.acquire_id(), 2);
+        fifo.release_id(2);
+        assert_eq!(fifo.acquire_id(), 5);
+        assert_eq!(fifo.acquire_id(), 2);
+        assert_eq!(fifo.acquire_id(), 10);
+        assert!(fifo.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn test_fifo_issued_ids_order() {
+        let mut cache = IdCache::with_capacity(3);
+        cache.set_reuse_policy(ReusePolicy::Fifo);
+        assert_eq!([cache.acquire_id(), cache.acquire_id(), cache.acquire_id()], [0, 1, 2]);
+
+        let mut cache = IdCache::fifo();
+        cache.acquire_id();
+        cache.acquire_id();
+        cache.release_id(0);
+        cache.grow(2);
+        assert_eq!([cache.acquire_id(), cache.acquire_id(), cache.acquire_id()], [0, 2, 3]);
+
+        let mut other = IdCache::new_starting_at(1);
+        other.acquire_id();
+        other.acquire_id();
+        other.release_id(2);
+        cache.release_id(1);
+        assert_eq!(cache.append(other), 4);
+        assert_eq!(
+            [cache.acquire_id(), cache.acquire_id(), cache.acquire_id(), cache.acquire_id()],
+            [1, 4, 6, 7]
+        );
+
+        cache.release_id(6);
+        cache.release_id(3);
+        cache.release_id(4);
+        cache.sort_free_list();
+        assert_eq!([cache.acquire_id(), cache.acquire_id(), cache.acquire_id()], [3, 4, 6]);
+
+        let checkpoint = cache.top_id();
+        cache.acquire_id();
+        cache.acquire_id();
+        cache.release_id(1);
+        cache.release_since(checkpoint);
+        assert_eq!([cache.acquire_id(), cache.acquire_id(), cache.acquire_id()], [1, 8, 9]);
+        assert!(cache.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn test_fifo_switch_keeps_order() {
+        let mut cache = IdCache::new();
+        for _ in 0..6 {
+            cache.acquire_id();
+        }
+
+        cache.release_id(4);
+        cache.release_id(1);
+        cache.set_reuse_policy(ReusePolicy::Fifo);
+        cache.release_id(3);
+        assert_eq!(cache.take_free_ids(), vec![3, 4, 1]);
+
+        cache.set_free_ids(vec![3, 4, 1]).unwrap();
+        assert_eq!([cache.acquire_id(), cache.acquire_id(), cache.acquire_id()], [1, 4, 3]);
+    }
 }
@@ -1,11 +1,50 @@
 mod cache_storage;
+mod error;
+mod frozen_storage;
+mod handle;
 mod id_cache;
+mod live_view;
+#[cfg(feature = "rayon")]
+mod par_iter;
+mod range_id_cache;
 mod shrinkable_storage;
 
 pub use crate::{
     id_cache::*,
-    cache_storage::CacheStorage,
-    shrinkable_storage::ShrinkableStorage,
+    cache_storage::{
+        move_element, CacheStorage, Cursor, Growth, IdState, SlotState, StorageState, StorageStats,
+    },
+    error::{CapacityError, DisjointError, FreeError, InsertError},
+    frozen_storage::FrozenStorage,
+    handle::{Handle, Key, OwnedKey},
+    live_view::LiveView,
+    range_id_cache::RangeIdCache,
+    shrinkable_storage::{
+        CompactProgress, GenId, ShrinkableIntoIter, ShrinkableStorage, ShrinkStrategy, WeakId,
+    },
 };
 
+#[cfg(not(feature = "small-ids"))]
 pub type Id = usize;
+
+/// With the `small-ids` feature ids take 4 bytes,
+/// so at most `u32::MAX + 1` (about 4 billion) ids can be issued.
+#[cfg(feature = "small-ids")]
+pub type Id = u32;
+
+#[inline]
+#[cfg_attr(not(feature = "small-ids"), allow(clippy::unnecessary_cast))]
+pub(crate) fn index(id: Id) -> usize {
+    id as usize
+}
+
+#[inline]
+#[cfg_attr(not(feature = "small-ids"), allow(clippy::unnecessary_cast))]
+pub(crate) fn to_id(index: usize) -> Id {
+    debug_assert!(index <= Id::MAX as usize, "index {} doesn't fit into an id", index);
+    index as Id
+}
+
+pub(crate) fn enumerate_ids<I: Iterator>(iter: I) -> impl Iterator<Item = (Id, I::Item)> {
+    iter.enumerate().map(|(index, item)| (to_id(index), item))
+}
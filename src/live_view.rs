@@ -0,0 +1,71 @@
+use super::{Id, index, enumerate_ids};
+
+/// Read-only view of the live elements of a `CacheStorage`.
+/// The free list is snapshotted into a sorted slice once,
+/// so every `contains` / `get` is a binary search.
+pub struct LiveView<'a, T> {
+    data: &'a [T],
+    free_ids: Box<[Id]>,
+}
+
+impl<'a, T> LiveView<'a, T> {
+    pub(crate) fn new<I: Iterator<Item = Id>>(data: &'a [T], free_ids: I) -> Self {
+        let mut free_ids: Box<[Id]> = free_ids.collect();
+        free_ids.sort_unstable();
+
+        Self {
+            data,
+            free_ids,
+        }
+    }
+
+    pub fn contains(&self, id: Id) -> bool {
+        index(id) < self.data.len() && self.free_ids.binary_search(&id).is_err()
+    }
+
+    pub fn get(&self, id: Id) -> Option<&'a T> {
+        if self.contains(id) {
+            Some(&self.data[index(id)])
+        } else {
+            None
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Id, &'a T)> + '_ {
+        let mut free_ids = self.free_ids.iter().peekable();
+
+        enumerate_ids(self.data.iter())
+            .filter(move |&(id, _)| {
+                while free_ids.next_if(|&&free_id| free_id < id).is_some() {}
+
+                free_ids.next_if_eq(&&id).is_none()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CacheStorage;
+
+    #[test]
+    fn test_live_view() {
+        let mut storage = CacheStorage::new();
+        storage.extend(0..8);
+        storage.remove(5);
+        storage.remove(1);
+        storage.remove(6);
+
+        let view = storage.live_view();
+        for id in 0..10 {
+            let is_live = id < 8 && ![1, 5, 6].contains(&id);
+            assert_eq!(view.contains(id), is_live);
+            assert_eq!(view.get(id), if is_live { Some(&id) } else { None });
+        }
+
+        assert_eq!(
+            view.iter().map(|(id, _)| id).collect::<Vec<_>>(),
+            vec![0, 2, 3, 4, 7]
+        );
+        assert_eq!(view.iter().count(), 5);
+    }
+}
@@ -0,0 +1,39 @@
+use {
+    super::{CacheStorage, Id},
+    rayon::{iter::IntoParallelIterator, vec::IntoIter},
+};
+
+/// Visits only the live elements.
+/// The live entries are collected up front, so freed slots are never seen by workers.
+impl<'a, T: Sync> IntoParallelIterator for &'a CacheStorage<T> {
+    type Iter = IntoIter<(Id, &'a T)>;
+    type Item = (Id, &'a T);
+
+    fn into_par_iter(self) -> Self::Iter {
+        self.live_entries()
+            .collect::<Vec<_>>()
+            .into_par_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{CacheStorage, index},
+        rayon::prelude::*,
+    };
+
+    #[test]
+    fn test_cache_storage_par_iter() {
+        let mut storage = CacheStorage::new();
+        storage.extend(0..1000usize);
+        for id in (0..1000).step_by(2) {
+            storage.remove(id);
+        }
+
+        let sum: usize = storage.par_iter().map(|(_, &obj)| obj).sum();
+        assert_eq!(sum, (0..1000).filter(|i| i % 2 == 1).sum());
+
+        assert!(storage.par_iter().all(|(id, &obj)| index(id) == obj && obj % 2 == 1));
+    }
+}
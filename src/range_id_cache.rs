@@ -0,0 +1,165 @@
+use {
+    super::{index, to_id, Id},
+    std::collections::BTreeMap,
+};
+
+/// `IdCache` alternative storing free ids as coalesced ranges,
+/// so a contiguous run of free ids takes a single entry.
+/// The lowest free id is acquired first.
+#[derive(Debug, Default)]
+pub struct RangeIdCache {
+    top_id: usize,
+    free_ids_num: usize,
+    free_ranges: BTreeMap<Id, Id>,
+}
+
+impl RangeIdCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn acquire_id(&mut self) -> Id {
+        match self.try_acquire_id() {
+            Some(id) => id,
+            None => {
+                let old_top_id = self.top_id;
+                self.top_id += 1;
+
+                to_id(old_top_id)
+            },
+        }
+    }
+
+    pub fn try_acquire_id(&mut self) -> Option<Id> {
+        let (&start, &end) = self.free_ranges.iter().next()?;
+        self.free_ranges.remove(&start);
+        if start + 1 < end {
+            self.free_ranges.insert(start + 1, end);
+        }
+
+        self.free_ids_num -= 1;
+
+        Some(start)
+    }
+
+    /// # Panics
+    /// [DEBUG CFG]
+    /// * If `id >= self.top_id`
+    /// * If `id` was already released
+    pub fn release_id(&mut self, id: Id) {
+        debug_assert!(
+            index(id) < self.top_id,
+            "id {} is out of range 0..{}",
+            id,
+            self.top_id
+        );
+
+        let mut start = id;
+        let mut end = id + 1;
+
+        if let Some((&prev_start, &prev_end)) = self.free_ranges.range(..=id).next_back() {
+            debug_assert!(prev_end <= id, "id {} double release", id);
+
+            if prev_end == id {
+                start = prev_start;
+            }
+        }
+
+        if let Some(next_end) = self.free_ranges.remove(&end) {
+            end = next_end;
+        }
+
+        self.free_ranges.insert(start, end);
+        self.free_ids_num += 1;
+    }
+
+    /// # Panics
+    /// [DEBUG CFG]
+    /// The same as `release_id` for every `id` from the `ids`.
+    pub fn release_ids<I: IntoIterator<Item = Id>>(&mut self, ids: I) {
+        for id in ids {
+            self.release_id(id);
+        }
+    }
+
+    pub fn is_free(&self, id: Id) -> bool {
+        self.free_ranges
+            .range(..=id)
+            .next_back()
+            .map_or(false, |(_, &end)| id < end)
+    }
+
+    pub fn reset(&mut self) {
+        self.top_id = 0;
+        self.free_ids_num = 0;
+        self.free_ranges.clear();
+    }
+
+    pub fn free_ids_num(&self) -> usize {
+        self.free_ids_num
+    }
+
+    /// Returns the number of stored free ranges.
+    pub fn free_ranges_num(&self) -> usize {
+        self.free_ranges.len()
+    }
+
+    pub fn top_id(&self) -> usize {
+        self.top_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{IdCache, RangeIdCache};
+
+    #[test]
+    fn test_range_id_cache() {
+        let mut cache = RangeIdCache::new();
+        assert!(cache.try_acquire_id().is_none());
+
+        for i in 0..6 {
+            assert_eq!(cache.acquire_id(), i);
+        }
+
+        cache.release_id(4);
+        cache.release_id(1);
+        cache.release_id(2);
+        assert_eq!(cache.free_ids_num(), 3);
+        assert_eq!(cache.free_ranges_num(), 2);
+        assert!(cache.is_free(2));
+        assert!(!cache.is_free(3));
+
+        cache.release_id(3);
+        assert_eq!(cache.free_ranges_num(), 1);
+
+        for i in 1..5 {
+            assert_eq!(cache.acquire_id(), i);
+        }
+
+        assert_eq!(cache.free_ids_num(), 0);
+        assert_eq!(cache.acquire_id(), 6);
+        assert_eq!(cache.top_id(), 7);
+
+        cache.reset();
+        assert_eq!(cache.acquire_id(), 0);
+    }
+
+    #[test]
+    fn test_range_id_cache_contiguous_release() {
+        let mut range_cache = RangeIdCache::new();
+        let mut cache = IdCache::new();
+        for _ in 0..10000 {
+            range_cache.acquire_id();
+            cache.acquire_id();
+        }
+
+        range_cache.release_ids(100..10000);
+        unsafe { cache.release_ids(100..10000) }
+
+        assert_eq!(range_cache.free_ids_num(), 9900);
+        assert_eq!(range_cache.free_ranges_num(), 1);
+        assert_eq!(cache.free_ids_num(), 9900);
+        assert_eq!(cache.free_ids.len(), 9900);
+    }
+}
@@ -1,62 +1,302 @@
 use {
-    super::Id,
+    super::{Id, FreeError, index, to_id, enumerate_ids},
     std::{
-        iter::Extend,
-        collections::BTreeSet,
+        iter::{Enumerate, Extend},
+        collections::{BTreeSet, HashMap, TryReserveError},
+        fmt,
+        mem,
+        ops::Range,
+        vec,
     }
 };
 
-#[derive(Debug, Clone)]
-pub struct ShrinkableStorage<T> {
+/// The optional `M` is metadata stored alongside every element,
+/// it moves together with its element when the storage is shrunk.
+pub struct ShrinkableStorage<T, M = ()> {
     data: Vec<T>,
-    free_ids: BTreeSet<Id>
+    meta: Vec<M>,
+    free_ids: BTreeSet<Id>,
+    auto_shrink: Option<AutoShrink>,
+    generations: Option<Vec<u32>>,
+    shrink_strategy: ShrinkStrategy,
+}
+
+/// How shrinking fills the freed slots.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ShrinkStrategy {
+    /// `swap_remove` of every freed element, from the highest id to the lowest.
+    /// An element pulled from the end may be moved again by a later `swap_remove`.
+    #[default]
+    SwapRemove,
+
+    /// The lowest freed slots are filled with the highest live elements.
+    /// Every element moves at most once, only the ones left above the new volume move.
+    FillFromTail,
+}
+
+/// Id along with the generation of its slot, see `ShrinkableStorage::set_track_generations`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GenId {
+    pub id: Id,
+    pub generation: u32,
+}
+
+/// Like `GenId`, but it is expected to dangle: check it with `ShrinkableStorage::is_alive`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WeakId {
+    pub id: Id,
+    pub generation: u32,
+}
+
+impl From<GenId> for WeakId {
+    fn from(gen_id: GenId) -> Self {
+        Self {
+            id: gen_id.id,
+            generation: gen_id.generation,
+        }
+    }
+}
+
+/// Result of a single `ShrinkableStorage::compact_step`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactProgress {
+    /// Maps ids before the step to ids after it for every moved element.
+    pub remap: HashMap<Id, Id>,
+
+    /// Whether there are no freed elements left.
+    pub done: bool,
+}
+
+type RemapCallback = Box<dyn FnMut(&HashMap<Id, Id>)>;
+
+struct AutoShrink {
+    ratio: f64,
+    on_remap: RemapCallback,
 }
 
 impl<T> ShrinkableStorage<T> {
     pub fn new() -> Self {
         Self {
             data: vec![],
+            meta: vec![],
             free_ids: BTreeSet::new(),
+            auto_shrink: None,
+            generations: None,
+            shrink_strategy: ShrinkStrategy::default(),
         }
     }
 
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
             data: Vec::with_capacity(capacity),
+            meta: vec![],
+            free_ids: BTreeSet::new(),
+            auto_shrink: None,
+            generations: None,
+            shrink_strategy: ShrinkStrategy::default(),
+        }
+    }
+
+    /// Same as `with_capacity`, but returns an error instead of aborting
+    /// if the memory can't be allocated.
+    pub fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError> {
+        let mut data = Vec::new();
+        data.try_reserve_exact(capacity)?;
+
+        Ok(Self {
+            data,
+            meta: vec![],
+            free_ids: BTreeSet::new(),
+            auto_shrink: None,
+            generations: None,
+            shrink_strategy: ShrinkStrategy::default(),
+        })
+    }
+}
+
+impl<T, M> ShrinkableStorage<T, M> {
+    /// Creates an empty storage with the metadata of type `M`.
+    pub fn with_meta() -> Self {
+        Self {
+            data: vec![],
+            meta: vec![],
             free_ids: BTreeSet::new(),
+            auto_shrink: None,
+            generations: None,
+            shrink_strategy: ShrinkStrategy::default(),
+        }
+    }
+
+    /// Makes `free_id`, `free_ids` and `try_free_ids` shrink the storage in place
+    /// when the ratio of free ids to `volume` exceeds `ratio`.
+    /// Since shrinking changes ids, `on_remap` is called with the map
+    /// from old ids to new ones of every remaining element.
+    ///
+    /// # Note
+    /// Unlike `shrink`, the auto-shrink doesn't require `T: Clone`.
+    pub fn set_auto_shrink<F>(&mut self, ratio: f64, on_remap: F)
+    where
+        F: FnMut(&HashMap<Id, Id>) + 'static
+    {
+        self.auto_shrink = Some(AutoShrink {
+            ratio,
+            on_remap: Box::new(on_remap),
+        });
+    }
+
+    pub fn disable_auto_shrink(&mut self) {
+        self.auto_shrink = None;
+    }
+
+    /// Sets how `shrink`, `shrink_with`, `shrink_to_vec` and the auto-shrink
+    /// fill the freed slots. The ids of the moved elements are reported either way.
+    pub fn set_shrink_strategy(&mut self, shrink_strategy: ShrinkStrategy) {
+        self.shrink_strategy = shrink_strategy;
+    }
+
+    /// Starts or stops counting the generation of every slot.
+    /// The generation is bumped whenever the slot is freed or an element moves
+    /// in or out of it during shrinking, so a `GenId` of a removed or moved element is rejected.
+    /// Without the tracking all generations are 0.
+    pub fn set_track_generations(&mut self, track_generations: bool) {
+        self.generations = if track_generations {
+            Some(self.generations.take().unwrap_or_default())
+        } else {
+            None
+        };
+    }
+
+    /// Returns the `GenId` of a non-freed element.
+    pub fn gen_id(&self, id: Id) -> Option<GenId> {
+        if index(id) >= self.data.len() || self.free_ids.contains(&id) {
+            return None;
+        }
+
+        Some(GenId {
+            id,
+            generation: self.generation(id),
+        })
+    }
+
+    /// Returns `None` if the element of `gen_id` was freed or moved.
+    pub fn get_by_gen(&self, gen_id: GenId) -> Option<&T> {
+        if self.gen_id(gen_id.id)? == gen_id {
+            Some(&self.data[index(gen_id.id)])
+        } else {
+            None
+        }
+    }
+
+    /// Frees the element of `gen_id`.
+    /// Returns `false` if it was already freed or moved.
+    pub fn free_gen(&mut self, gen_id: GenId) -> bool {
+        if self.gen_id(gen_id.id) == Some(gen_id) {
+            self.free_id(gen_id.id);
+            true
+        } else {
+            false
         }
     }
 
+    /// Returns the `WeakId` of a non-freed element.
+    pub fn weak_id(&self, id: Id) -> Option<WeakId> {
+        self.gen_id(id).map(WeakId::from)
+    }
+
+    /// Checks whether the element of `weak_id` is still stored at its id.
+    pub fn is_alive(&self, weak_id: WeakId) -> bool {
+        self.weak_id(weak_id.id) == Some(weak_id)
+    }
+
+    fn generation(&self, id: Id) -> u32 {
+        self.generations
+            .as_ref()
+            .and_then(|generations| generations.get(index(id)).copied())
+            .unwrap_or(0)
+    }
+
     pub fn volume(&self) -> usize {
         self.data.len()
     }
 
+    /// Returns the ratio of freed elements to `volume`, 0 for an empty storage.
+    pub fn fragmentation(&self) -> f64 {
+        if self.data.is_empty() {
+            0.0
+        } else {
+            self.free_ids.len() as f64 / self.data.len() as f64
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
         self.data.is_empty()
     }
 
-    pub fn insert(&mut self, obj: T) -> Id {
-        let id = self.data.len();
+    pub fn insert(&mut self, obj: T) -> Id
+    where
+        M: Default
+    {
+        self.insert_with_meta(obj, M::default())
+    }
+
+    /// # Panics
+    /// If the id space is exhausted, like `IdCache::acquire_id`.
+    pub fn insert_with_meta(&mut self, obj: T, meta: M) -> Id {
+        assert_id_space(self.data.len(), 1);
+        let id = to_id(self.data.len());
         self.data.push(obj);
+        self.meta.push(meta);
 
         id
     }
 
+    /// Appends `n` elements made by `make` from their ids.
+    /// Returns the contiguous range of their ids.
+    ///
+    /// # Panics
+    /// If the id space is exhausted, like `IdCache::acquire_id`.
+    pub fn reserve_slots_with<F: FnMut(Id) -> T>(&mut self, n: usize, mut make: F) -> Range<Id>
+    where
+        M: Default
+    {
+        let start = self.data.len();
+        assert_id_space(start, n);
+        self.data.extend((start..start + n).map(|i| make(to_id(i))));
+        self.meta.resize_with(start + n, M::default);
+
+        to_id(start)..to_id(start + n)
+    }
+
     pub fn get(&self, id: Id) -> &T {
-        &self.data[id]
+        &self.data[index(id)]
     }
 
     pub fn get_mut(&mut self, id: Id) -> &mut T {
-        &mut self.data[id]
+        &mut self.data[index(id)]
+    }
+
+    pub fn meta(&self, id: Id) -> &M {
+        &self.meta[index(id)]
+    }
+
+    pub fn meta_mut(&mut self, id: Id) -> &mut M {
+        &mut self.meta[index(id)]
     }
 
     /// # Panics
     /// [DEBUG CFG]
     /// * If `id >= self.data.len()`
     pub fn free_id(&mut self, id: Id) {
-        debug_assert!(id < self.data.len());
+        debug_assert!(
+            index(id) < self.data.len(),
+            "id {} is out of range 0..{}",
+            id,
+            self.data.len()
+        );
 
         self.free_ids.insert(id);
+        bump(&mut self.generations, id);
+        self.auto_shrink_if_needed();
     }
 
     /// # Panics
@@ -65,68 +305,521 @@ impl<T> ShrinkableStorage<T> {
         let ids = ids.into_iter();
 
         let last_id = self.data.len();
+        let generations = &mut self.generations;
         self.free_ids.extend(ids.inspect(|&id| {
-            debug_assert!(id < last_id);
+            debug_assert!(index(id) < last_id, "id {} is out of range 0..{}", id, last_id);
+            bump(generations, id);
         }));
+        self.auto_shrink_if_needed();
+    }
+
+    /// Frees the `ids` only if every one of them is in range and not free yet.
+    /// Returns the number of newly freed ids.
+    pub fn try_free_ids<I>(&mut self, ids: I) -> Result<usize, FreeError>
+    where
+        I: IntoIterator<Item=Id>
+    {
+        let mut new_free_ids = BTreeSet::new();
+        for id in ids {
+            if index(id) >= self.data.len() {
+                return Err(FreeError::OutOfRange(id));
+            }
+
+            if self.free_ids.contains(&id) || !new_free_ids.insert(id) {
+                return Err(FreeError::AlreadyFree(id));
+            }
+        }
+
+        let freed_num = new_free_ids.len();
+        for &id in new_free_ids.iter() {
+            bump(&mut self.generations, id);
+        }
+        self.free_ids.append(&mut new_free_ids);
+        self.auto_shrink_if_needed();
+
+        Ok(freed_num)
     }
 
     pub fn is_id_free(&self, id: &Id) -> bool {
         self.free_ids.contains(id)
     }
 
+    /// Returns the non-freed element with the lowest id.
+    pub fn first(&self) -> Option<(Id, &T)> {
+        (0..self.data.len())
+            .map(to_id)
+            .find(|id| !self.free_ids.contains(id))
+            .map(|id| (id, &self.data[index(id)]))
+    }
+
+    /// Returns the non-freed element with the highest id.
+    pub fn last(&self) -> Option<(Id, &T)> {
+        (0..self.data.len())
+            .rev()
+            .map(to_id)
+            .find(|id| !self.free_ids.contains(id))
+            .map(|id| (id, &self.data[index(id)]))
+    }
+
     /// # Safety
     /// This function will not free the ids.
-    pub unsafe fn retain<P>(&mut self, predicate: P)
+    pub unsafe fn retain<P>(&mut self, mut predicate: P)
     where
         P: FnMut(&T) -> bool
     {
-        self.data.retain(predicate);
+        let mut kept = Vec::with_capacity(self.data.len());
+        self.data.retain(|obj| {
+            let keep = predicate(obj);
+            kept.push(keep);
+            keep
+        });
+
+        let mut kept = kept.into_iter();
+        self.meta.retain(|_| kept.next().unwrap_or(false));
+    }
+
+    /// Drops every element, so the next `insert` returns 0 again.
+    /// The elements keep their capacity.
+    ///
+    /// # Note
+    /// The generations of the dropped slots are bumped,
+    /// so the `GenId`s taken before the reset stay rejected.
+    pub fn reset(&mut self) {
+        for id in self.iter_ids() {
+            bump(&mut self.generations, id);
+        }
+
+        self.data.clear();
+        self.meta.clear();
+        self.free_ids.clear();
     }
 
+    /// # Note
+    /// The `GenId`s taken before freeing stay rejected, see `set_track_generations`.
     pub fn restore_freed(&mut self) {
         self.free_ids.clear();
     }
 
     pub fn iter(&self) -> impl Iterator<Item=(Id, &T)> {
-        self.data.iter().enumerate()
+        enumerate_ids(self.data.iter())
     }
 
     pub fn iter_ids(&self) -> impl Iterator<Item=Id> {
-        0..self.data.len()
+        (0..self.data.len()).map(to_id)
+    }
+
+    /// Yields the non-freed ids in ascending order.
+    /// The ids and the sorted free ids are walked in lockstep,
+    /// so it is O(volume) without a lookup per id (see `benches/live_ids.rs`).
+    pub fn live_ids(&self) -> impl Iterator<Item=Id> + '_ {
+        let mut free_ids = self.free_ids.iter().peekable();
+
+        self.iter_ids().filter(move |id| free_ids.next_if_eq(&id).is_none())
+    }
+
+    /// Consumes the storage and yields the non-freed elements in ascending id order.
+    /// Unlike `shrink`, the order of the survivors is stable.
+    /// The freed elements are dropped.
+    pub fn into_survivors(self) -> impl Iterator<Item=T> {
+        self.into_iter().map(|(_, obj)| obj)
+    }
+
+    /// Consumes the storage and returns the non-freed elements densely packed
+    /// the same way as `shrink` does, along with the map from old ids
+    /// to indices in the returned `Vec`.
+    pub fn shrink_to_vec(mut self) -> (Vec<T>, HashMap<Id, Id>) {
+        let remap = self.shrink_in_place();
+
+        (self.data, remap)
+    }
+
+    /// Removes the freed elements in place the same way as `shrink` does
+    /// and releases the memory, keeping room for `spare` more elements.
+    /// Returns the map from old ids to new ones of every remaining element.
+    pub fn shrink_keeping_spare(&mut self, spare: usize) -> HashMap<Id, Id> {
+        let remap = self.shrink_in_place();
+
+        let capacity = self.data.len() + spare;
+        if self.data.capacity() > capacity {
+            self.data.shrink_to(capacity);
+            self.meta.shrink_to(capacity);
+        } else {
+            self.data.reserve_exact(spare);
+            self.meta.reserve_exact(spare);
+        }
+
+        remap
+    }
+
+    /// Passes `fragmentation` to `should` and, if it returns `true`,
+    /// removes the freed elements in place the same way as `shrink` does.
+    /// Returns the map from old ids to new ones of every remaining element,
+    /// or `None` if the storage wasn't shrunk.
+    pub fn shrink_if<F: FnOnce(f64) -> bool>(&mut self, should: F) -> Option<HashMap<Id, Id>> {
+        if should(self.fragmentation()) {
+            Some(self.shrink_in_place())
+        } else {
+            None
+        }
+    }
+
+    /// Removes the freed elements in place the same way as `shrink` does
+    /// and calls `on_move(old_id, new_id)` for every relocated element.
+    /// The elements that stayed put are not reported.
+    pub fn shrink_with<F: FnMut(Id, Id)>(&mut self, mut on_move: F) {
+        for (new_id, old_id) in enumerate_ids(self.compact().into_iter()) {
+            if old_id != new_id {
+                on_move(old_id, new_id);
+            }
+        }
+    }
+
+    /// Removes up to `budget` freed elements the same way as `shrink` does.
+    /// Calling it until `done` gives the same result as a single `shrink`,
+    /// so the compaction can be spread over several calls.
+    /// With `ShrinkStrategy::FillFromTail` the freed slots at the end
+    /// are removed on top of the budget, as they don't move anything.
+    ///
+    /// # Note
+    /// The remap of each step is relative to the ids before that step,
+    /// so the remaps of successive steps have to be chained.
+    pub fn compact_step(&mut self, budget: usize) -> CompactProgress {
+        let fill_from_tail = self.shrink_strategy == ShrinkStrategy::FillFromTail;
+
+        let mut old_ids = HashMap::new();
+        for _ in 0..budget {
+            let next_id = if fill_from_tail {
+                self.pop_free_tail(0);
+                self.free_ids.iter().next()
+            } else {
+                self.free_ids.iter().next_back()
+            };
+
+            let id = match next_id {
+                Some(&id) => id,
+                None => break,
+            };
+
+            self.free_ids.remove(&id);
+
+            let last_id = to_id(self.data.len() - 1);
+            self.data.swap_remove(index(id));
+            self.meta.swap_remove(index(id));
+            if id != last_id {
+                bump(&mut self.generations, id);
+                bump(&mut self.generations, last_id);
+                let old_id = old_ids.remove(&last_id).unwrap_or(last_id);
+                old_ids.insert(id, old_id);
+            }
+        }
+
+        if fill_from_tail {
+            self.pop_free_tail(0);
+        }
+
+        CompactProgress {
+            remap: old_ids.into_iter().map(|(new_id, old_id)| (old_id, new_id)).collect(),
+            done: self.free_ids.is_empty(),
+        }
+    }
+
+    /// Compacts only the freed ids within `range`, leaving the rest untouched.
+    /// Returns the map from old ids to new ones of the moved elements.
+    ///
+    /// # Strategy
+    /// Only the survivors within `range` are moved: the highest of them fill
+    /// the lowest freed slots of the range, so the freed ids end up
+    /// at the end of the range and stay freed.
+    /// If the range reaches the end of the storage, those trailing freed slots
+    /// are removed, otherwise the volume doesn't change.
+    pub fn shrink_range(&mut self, range: Range<Id>) -> HashMap<Id, Id> {
+        let start = index(range.start).min(self.data.len());
+        let end = index(range.end).min(self.data.len());
+
+        let remap = self.fill_holes(start, end, &BTreeSet::new());
+        if end == self.data.len() {
+            self.pop_free_tail(start);
+        }
+
+        remap
+    }
+
+    /// Moves the highest live non-pinned elements of `start..end`
+    /// into the lowest freed slots of it.
+    /// Returns the map from old ids to new ones of the moved elements.
+    fn fill_holes(&mut self, start: usize, end: usize, pinned: &BTreeSet<Id>) -> HashMap<Id, Id> {
+        let holes: Vec<Id> = self.free_ids.range(to_id(start)..to_id(end)).copied().collect();
+
+        let mut remap = HashMap::new();
+        let mut high = end;
+        for hole in holes {
+            while high > start
+                && (self.free_ids.contains(&to_id(high - 1)) || pinned.contains(&to_id(high - 1)))
+            {
+                high -= 1;
+            }
+
+            if high == start || index(hole) >= high {
+                break;
+            }
+
+            high -= 1;
+            let src = to_id(high);
+            self.data.swap(index(hole), high);
+            self.meta.swap(index(hole), high);
+            self.free_ids.remove(&hole);
+            self.free_ids.insert(src);
+            bump(&mut self.generations, hole);
+            bump(&mut self.generations, src);
+            remap.insert(src, hole);
+        }
+
+        remap
+    }
+
+    /// Removes the freed slots at the end of the storage, but not below `floor`.
+    fn pop_free_tail(&mut self, floor: usize) {
+        while let Some(&last_id) = self.free_ids.iter().next_back() {
+            if index(last_id) + 1 != self.data.len() || index(last_id) < floor {
+                break;
+            }
+
+            self.free_ids.remove(&last_id);
+            self.data.pop();
+            self.meta.pop();
+        }
+    }
+
+    /// Removes the freed elements in place the same way as `shrink` does.
+    /// Returns the map from old ids to new ones of every remaining element.
+    fn shrink_in_place(&mut self) -> HashMap<Id, Id> {
+        enumerate_ids(self.compact().into_iter())
+            .map(|(new_id, old_id)| (old_id, new_id))
+            .collect()
+    }
+
+    /// Returns the old ids indexed by the new ones.
+    fn compact(&mut self) -> Vec<Id> {
+        let mut old_ids: Vec<Id> = self.iter_ids().collect();
+
+        if self.shrink_strategy == ShrinkStrategy::FillFromTail {
+            let new_len = self.data.len() - self.free_ids.len();
+
+            let mut tail_free_ids = self.free_ids.range(to_id(new_len)..).rev().peekable();
+            let mut high = self.data.len();
+            for &hole in self.free_ids.range(..to_id(new_len)) {
+                high -= 1;
+                while tail_free_ids.next_if(|&&id| index(id) == high).is_some() {
+                    high -= 1;
+                }
+
+                self.data.swap(index(hole), high);
+                self.meta.swap(index(hole), high);
+                old_ids[index(hole)] = to_id(high);
+                bump(&mut self.generations, hole);
+                bump(&mut self.generations, to_id(high));
+            }
+
+            self.data.truncate(new_len);
+            self.meta.truncate(new_len);
+            old_ids.truncate(new_len);
+            self.free_ids.clear();
+
+            return old_ids;
+        }
+
+        let mut iter = self.free_ids.iter();
+        while let Some(&id) = iter.next_back() {
+            self.data.swap_remove(index(id));
+            self.meta.swap_remove(index(id));
+            old_ids.swap_remove(index(id));
+
+            if index(id) < self.data.len() {
+                bump(&mut self.generations, id);
+                bump(&mut self.generations, to_id(self.data.len()));
+            }
+        }
+
+        self.free_ids.clear();
+
+        old_ids
+    }
+
+    fn auto_shrink_if_needed(&mut self) {
+        let needs_shrink = match &self.auto_shrink {
+            Some(auto_shrink) => self.fragmentation() > auto_shrink.ratio,
+            None => false,
+        };
+
+        if needs_shrink {
+            let remap = self.shrink_in_place();
+            if let Some(auto_shrink) = &mut self.auto_shrink {
+                (auto_shrink.on_remap)(&remap);
+            }
+        }
+    }
+}
+
+/// Panics unless `additional` more elements after `len` get ids below `Id::MAX`.
+fn assert_id_space(len: usize, additional: usize) {
+    assert!(
+        len.checked_add(additional).map_or(false, |len| len <= index(Id::MAX)),
+        "id space is exhausted"
+    );
+}
+
+fn bump(generations: &mut Option<Vec<u32>>, id: Id) {
+    if let Some(generations) = generations {
+        if generations.len() <= index(id) {
+            generations.resize(index(id) + 1, 0);
+        }
+
+        generations[index(id)] = generations[index(id)].wrapping_add(1);
     }
 }
 
-impl<T: Clone> ShrinkableStorage<T> {
+impl<T: Clone, M> ShrinkableStorage<T, M> {
+    /// Returns a clone of the value or `None` if `id` is out of range or freed.
+    pub fn get_cloned(&self, id: Id) -> Option<T> {
+        if self.free_ids.contains(&id) {
+            None
+        } else {
+            self.data.get(index(id)).cloned()
+        }
+    }
+}
+
+impl<T: Clone, M: Clone> ShrinkableStorage<T, M> {
     /// Returns new storage without freed elements.
     /// # Note
     /// Ids in the new storage will change.
     pub fn shrink(&self) -> Self {
         let mut storage = self.clone();
+        storage.compact();
 
-        let mut iter = storage.free_ids.iter();
-        while let Some(&id) = iter.next_back() {
-            storage.data.swap_remove(id);
+        storage
+    }
+
+    /// Returns new storage compacted like `shrink_range` over the whole storage,
+    /// but the live `pinned` elements keep their ids.
+    /// The freed slots below the pinned elements that can't be filled
+    /// stay freed in the new storage.
+    /// Returns the map from old ids to new ones of the moved elements.
+    pub fn shrink_pinning(&self, pinned: &BTreeSet<Id>) -> (Self, HashMap<Id, Id>) {
+        let mut storage = self.clone();
+
+        let remap = storage.fill_holes(0, storage.data.len(), pinned);
+        storage.pop_free_tail(0);
+
+        (storage, remap)
+    }
+}
+
+impl<T: Default, M: Default> ShrinkableStorage<T, M> {
+    /// Appends `n` default elements, see `reserve_slots_with`.
+    pub fn reserve_slots(&mut self, n: usize) -> Range<Id> {
+        self.reserve_slots_with(n, |_| T::default())
+    }
+}
+
+impl<T: Default, M> ShrinkableStorage<T, M> {
+    /// Moves the element out, replacing it with `T::default()`, and frees its id.
+    /// Returns `None` if `id` is already free or out of range.
+    pub fn take(&mut self, id: Id) -> Option<T> {
+        if index(id) >= self.data.len() || self.free_ids.contains(&id) {
+            return None;
         }
 
-        storage.free_ids.clear();
+        let obj = mem::take(&mut self.data[index(id)]);
+        self.free_id(id);
 
-        storage
+        Some(obj)
+    }
+}
+
+/// The auto-shrink setting is not cloned.
+impl<T: Clone, M: Clone> Clone for ShrinkableStorage<T, M> {
+    fn clone(&self) -> Self {
+        Self {
+            data: self.data.clone(),
+            meta: self.meta.clone(),
+            free_ids: self.free_ids.clone(),
+            auto_shrink: None,
+            generations: self.generations.clone(),
+            shrink_strategy: self.shrink_strategy,
+        }
+    }
+}
+
+impl<T: fmt::Debug, M: fmt::Debug> fmt::Debug for ShrinkableStorage<T, M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShrinkableStorage")
+            .field("data", &self.data)
+            .field("meta", &self.meta)
+            .field("free_ids", &self.free_ids)
+            .field("auto_shrink_ratio", &self.auto_shrink.as_ref().map(|a| a.ratio))
+            .field("generations", &self.generations)
+            .field("shrink_strategy", &self.shrink_strategy)
+            .finish()
     }
 }
 
-impl<T> Extend<T> for ShrinkableStorage<T> {
+impl<T, M> Default for ShrinkableStorage<T, M> {
+    fn default() -> Self {
+        Self::with_meta()
+    }
+}
+
+impl<T, M: Default> Extend<T> for ShrinkableStorage<T, M> {
     fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
         self.data.extend(iter);
+        assert_id_space(self.data.len(), 0);
+        self.meta.resize_with(self.data.len(), M::default);
+    }
+}
+
+/// Consumes the storage and yields the non-freed `(Id, T)` in ascending id order.
+/// The freed elements are dropped.
+impl<T, M> IntoIterator for ShrinkableStorage<T, M> {
+    type Item = (Id, T);
+    type IntoIter = ShrinkableIntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        ShrinkableIntoIter {
+            data: self.data.into_iter().enumerate(),
+            free_ids: self.free_ids,
+        }
+    }
+}
+
+pub struct ShrinkableIntoIter<T> {
+    data: Enumerate<vec::IntoIter<T>>,
+    free_ids: BTreeSet<Id>,
+}
+
+impl<T> Iterator for ShrinkableIntoIter<T> {
+    type Item = (Id, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let free_ids = &self.free_ids;
+
+        self.data
+            .by_ref()
+            .map(|(index, obj)| (to_id(index), obj))
+            .find(|(id, _)| !free_ids.contains(id))
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::clone_on_copy, suspicious_double_ref_op)]
 mod tests {
     use {
-        crate::ShrinkableStorage,
+        crate::{ShrinkableStorage, ShrinkStrategy, FreeError, WeakId, Id, index},
         std::{
-            collections::HashSet,
+            cell::RefCell,
+            collections::{BTreeSet, HashMap, HashSet},
             iter::once,
+            rc::Rc,
         }
     };
 
@@ -191,4 +884,547 @@ mod tests {
         assert!(!new_storage.is_empty());
         assert_eq!(new_storage.volume(), new_data.len());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_shrinkable_storage_try_with_capacity() {
+        let storage = ShrinkableStorage::<i32>::try_with_capacity(10).unwrap();
+        assert!(storage.data.capacity() >= 10);
+        assert!(storage.is_empty());
+
+        assert!(ShrinkableStorage::<i32>::try_with_capacity(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_shrinkable_storage_try_free_ids() {
+        let mut storage = ShrinkableStorage::new();
+        storage.extend(0..5);
+
+        assert_eq!(storage.try_free_ids(vec![1, 3]), Ok(2));
+        assert!(storage.is_id_free(&1));
+        assert!(storage.is_id_free(&3));
+
+        assert_eq!(storage.try_free_ids(vec![0, 5]), Err(FreeError::OutOfRange(5)));
+        assert_eq!(storage.try_free_ids(vec![0, 3]), Err(FreeError::AlreadyFree(3)));
+        assert_eq!(storage.try_free_ids(vec![0, 0]), Err(FreeError::AlreadyFree(0)));
+        assert!(!storage.is_id_free(&0));
+        assert_eq!(storage.free_ids.len(), 2);
+
+        assert_eq!(storage.try_free_ids(vec![4, 0]), Ok(2));
+        assert_eq!(storage.free_ids.len(), 4);
+    }
+
+    #[test]
+    fn test_shrinkable_storage_auto_shrink() {
+        let remaps = Rc::new(RefCell::new(vec![]));
+
+        let mut storage = ShrinkableStorage::new();
+        storage.extend(0..10);
+        storage.set_auto_shrink(0.25, {
+            let remaps = remaps.clone();
+            move |remap: &HashMap<_, _>| remaps.borrow_mut().push(remap.clone())
+        });
+
+        storage.free_ids(vec![1, 2]);
+        assert_eq!(storage.volume(), 10);
+        assert!(remaps.borrow().is_empty());
+
+        storage.free_id(8);
+        assert_eq!(storage.volume(), 7);
+        assert!(storage.free_ids.is_empty());
+        assert_eq!(remaps.borrow().len(), 1);
+
+        let remap = &remaps.borrow()[0];
+        assert_eq!(remap.len(), 7);
+        for (old_id, &new_id) in remap {
+            assert_eq!(*storage.get(new_id), *old_id);
+        }
+
+        storage.disable_auto_shrink();
+        storage.free_ids(vec![0, 1, 2]);
+        assert_eq!(storage.volume(), 7);
+        assert_eq!(remaps.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_shrinkable_storage_take() {
+        let mut storage = ShrinkableStorage::new();
+        storage.extend(vec![String::from("a"), String::from("b")]);
+
+        assert_eq!(storage.take(1), Some(String::from("b")));
+        assert!(storage.is_id_free(&1));
+        assert!(storage.get(1).is_empty());
+
+        assert_eq!(storage.take(1), None);
+        assert_eq!(storage.take(2), None);
+        assert_eq!(storage.free_ids.len(), 1);
+        assert_eq!(storage.get(0), "a");
+    }
+
+    #[test]
+    fn test_shrinkable_storage_into_survivors() {
+        let mut storage = ShrinkableStorage::new();
+        storage.extend(vec!['a', 'b', 'c', 'd', 'e']);
+        storage.free_ids(vec![0, 3]);
+
+        let survivors: Vec<_> = storage.into_survivors().collect();
+        assert_eq!(survivors, vec!['b', 'c', 'e']);
+    }
+
+    #[test]
+    fn test_shrinkable_storage_shrink_with() {
+        let mut storage = ShrinkableStorage::new();
+        storage.extend(vec!['a', 'b', 'c', 'd', 'e', 'f']);
+        storage.free_ids(vec![1, 4, 5]);
+
+        let mut moves = vec![];
+        storage.shrink_with(|old_id, new_id| moves.push((old_id, new_id)));
+
+        assert_eq!(moves, vec![(3, 1)]);
+        assert_eq!(storage.iter().map(|(_, &obj)| obj).collect::<Vec<_>>(), vec!['a', 'd', 'c']);
+        assert!(storage.free_ids.is_empty());
+
+        let mut storage = ShrinkableStorage::new();
+        storage.extend(vec!['a', 'b', 'c']);
+        storage.free_ids(vec![0, 1]);
+
+        let mut moves = vec![];
+        storage.shrink_with(|old_id, new_id| moves.push((old_id, new_id)));
+        assert_eq!(moves, vec![(2, 0)]);
+        assert_eq!(*storage.get(0), 'c');
+    }
+
+    #[test]
+    fn test_shrinkable_storage_shrink_to_vec() {
+        let mut storage = ShrinkableStorage::new();
+        storage.extend(vec!['a', 'b', 'c', 'd', 'e', 'f']);
+        storage.free_ids(vec![0, 2, 3]);
+
+        let (data, remap) = storage.shrink_to_vec();
+        assert_eq!(data.len(), 3);
+        assert_eq!(data.iter().collect::<HashSet<_>>(), ['b', 'e', 'f'].iter().collect());
+
+        assert_eq!(remap.len(), 3);
+        for &(old_id, obj) in [(1, 'b'), (4, 'e'), (5, 'f')].iter() {
+            assert_eq!(data[index(remap[&old_id])], obj);
+        }
+    }
+
+    #[test]
+    fn test_shrinkable_storage_first_last() {
+        let mut storage = ShrinkableStorage::new();
+        assert_eq!(storage.first(), None);
+        assert_eq!(storage.last(), None);
+
+        storage.extend(vec!['a', 'b', 'c', 'd']);
+        assert_eq!(storage.first(), Some((0, &'a')));
+        assert_eq!(storage.last(), Some((3, &'d')));
+
+        storage.free_ids(vec![0, 3]);
+        assert_eq!(storage.first(), Some((1, &'b')));
+        assert_eq!(storage.last(), Some((2, &'c')));
+
+        storage.free_ids(vec![1, 2]);
+        assert_eq!(storage.first(), None);
+        assert_eq!(storage.last(), None);
+    }
+
+    #[test]
+    fn test_shrinkable_storage_shrink_range() {
+        let mut storage = ShrinkableStorage::new();
+        storage.extend(vec!['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h']);
+        storage.free_ids(vec![1, 2, 4, 6]);
+
+        let remap = storage.shrink_range(0..5);
+        assert_eq!(remap, [(3, 1)].iter().copied().collect());
+        assert_eq!(storage.volume(), 8);
+        assert_eq!(storage.free_ids.iter().copied().collect::<Vec<_>>(), vec![2, 3, 4, 6]);
+        assert_eq!(*storage.get(1), 'd');
+        assert_eq!(*storage.get(5), 'f');
+        assert_eq!(*storage.get(7), 'h');
+
+        let remap = storage.shrink_range(5..8);
+        assert_eq!(remap, [(7, 6)].iter().copied().collect());
+        assert_eq!(storage.volume(), 7);
+        assert_eq!(*storage.get(6), 'h');
+        assert_eq!(storage.free_ids.iter().copied().collect::<Vec<_>>(), vec![2, 3, 4]);
+
+        assert!(storage.shrink_range(2..5).is_empty());
+        assert_eq!(storage.volume(), 7);
+    }
+
+    #[test]
+    fn test_shrinkable_storage_into_iter() {
+        let mut storage = ShrinkableStorage::new();
+        storage.extend(vec![String::from("a"), String::from("b"), String::from("c"), String::from("d")]);
+        storage.free_ids(vec![0, 2]);
+
+        let survivors: Vec<_> = storage.into_iter().collect();
+        assert_eq!(survivors, vec![(1, String::from("b")), (3, String::from("d"))]);
+    }
+
+    #[test]
+    fn test_shrinkable_storage_get_cloned() {
+        let mut storage = ShrinkableStorage::new();
+        storage.extend(vec![String::from("a"), String::from("b")]);
+        storage.free_id(0);
+
+        assert_eq!(storage.get_cloned(0), None);
+        assert_eq!(storage.get_cloned(1), Some(String::from("b")));
+        assert_eq!(storage.get_cloned(2), None);
+    }
+
+    #[test]
+    fn test_shrinkable_storage_shrink_pinning() {
+        let mut storage = ShrinkableStorage::new();
+        storage.extend(vec!['a', 'b', 'c', 'd', 'e', 'f', 'g']);
+        storage.free_ids(vec![0, 1, 3]);
+
+        let pinned = [4, 6].iter().copied().collect();
+        let (new_storage, remap) = storage.shrink_pinning(&pinned);
+
+        assert_eq!(remap, [(5, 0), (2, 1)].iter().copied().collect());
+        assert_eq!(new_storage.volume(), 7);
+        assert_eq!(*new_storage.get(0), 'f');
+        assert_eq!(*new_storage.get(1), 'c');
+        assert_eq!(*new_storage.get(4), 'e');
+        assert_eq!(*new_storage.get(6), 'g');
+        assert_eq!(new_storage.free_ids.iter().copied().collect::<Vec<_>>(), vec![2, 3, 5]);
+        assert_eq!(storage.free_ids.len(), 3);
+
+        let (new_storage, remap) = storage.shrink_pinning(&BTreeSet::new());
+        assert_eq!(remap.len(), 3);
+        assert_eq!(new_storage.volume(), 4);
+        assert!(new_storage.free_ids.is_empty());
+    }
+
+    #[test]
+    fn test_shrinkable_storage_compact_step() {
+        let src = vec!['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h'];
+        let free_ids = vec![0, 2, 3, 6];
+
+        for &(shrink_strategy, expected_steps) in [
+            (ShrinkStrategy::SwapRemove, 2),
+            (ShrinkStrategy::FillFromTail, 1),
+        ].iter() {
+            let mut expected = ShrinkableStorage::new();
+            expected.set_shrink_strategy(shrink_strategy);
+            expected.extend(src.clone());
+            expected.free_ids(free_ids.clone());
+            let expected = expected.shrink();
+
+            let mut storage = ShrinkableStorage::new();
+            storage.set_shrink_strategy(shrink_strategy);
+            storage.extend(src.clone());
+            storage.free_ids(free_ids.clone());
+
+            let mut ids: HashMap<_, _> = storage.iter_ids().map(|id| (id, id)).collect();
+            let mut steps = 0;
+            loop {
+                let progress = storage.compact_step(3);
+                steps += 1;
+
+                for current_id in ids.values_mut() {
+                    if let Some(&new_id) = progress.remap.get(current_id) {
+                        *current_id = new_id;
+                    }
+                }
+
+                if progress.done {
+                    break;
+                }
+            }
+
+            assert_eq!(steps, expected_steps);
+            assert_eq!(
+                storage.iter().map(|(_, &obj)| obj).collect::<Vec<_>>(),
+                expected.iter().map(|(_, &obj)| obj).collect::<Vec<_>>()
+            );
+
+            for &old_id in [1, 4, 5, 7].iter() {
+                assert_eq!(*storage.get(ids[&old_id]), src[index(old_id)]);
+            }
+
+            assert!(storage.compact_step(3).done);
+        }
+    }
+
+    #[test]
+    fn test_shrinkable_storage_generations() {
+        let mut storage = ShrinkableStorage::new();
+        storage.set_track_generations(true);
+        storage.extend(vec!['a', 'b', 'c', 'd']);
+
+        let a = storage.gen_id(0).unwrap();
+        let b = storage.gen_id(1).unwrap();
+        let d = storage.gen_id(3).unwrap();
+        assert_eq!(storage.get_by_gen(b), Some(&'b'));
+
+        assert!(storage.free_gen(b));
+        assert!(!storage.free_gen(b));
+        assert_eq!(storage.get_by_gen(b), None);
+        assert_eq!(storage.gen_id(1), None);
+
+        let storage = storage.shrink();
+        assert_eq!(storage.get(1), &'d');
+        assert_eq!(storage.get_by_gen(b), None);
+        assert_eq!(storage.get_by_gen(d), None);
+        assert_eq!(storage.get_by_gen(a), Some(&'a'));
+
+        let mut storage = storage;
+        storage.insert('e');
+        assert_eq!(storage.get_by_gen(d), None);
+        assert_eq!(storage.get_by_gen(storage.gen_id(3).unwrap()), Some(&'e'));
+    }
+
+    #[test]
+    fn test_shrinkable_storage_shrink_strategy() {
+        let make_storage = |shrink_strategy| {
+            let mut storage = ShrinkableStorage::new();
+            storage.set_shrink_strategy(shrink_strategy);
+            storage.extend(0..10);
+            storage.free_ids(vec![0, 2, 6, 8]);
+            storage
+        };
+
+        let mut moves = vec![];
+        make_storage(ShrinkStrategy::SwapRemove).shrink_with(|old_id, new_id| moves.push((old_id, new_id)));
+        moves.sort_unstable();
+        assert_eq!(moves, vec![(7, 2), (9, 0)]);
+
+        let mut storage = make_storage(ShrinkStrategy::FillFromTail);
+        let mut moves = vec![];
+        storage.shrink_with(|old_id, new_id| moves.push((old_id, new_id)));
+        moves.sort_unstable();
+        assert_eq!(moves, vec![(7, 2), (9, 0)]);
+        assert_eq!(storage.iter().map(|(_, &obj)| obj).collect::<Vec<_>>(), vec![9, 1, 7, 3, 4, 5]);
+        assert!(storage.free_ids.is_empty());
+
+        let (data, remap) = make_storage(ShrinkStrategy::FillFromTail).shrink_to_vec();
+        assert_eq!(remap.len(), 6);
+        for (old_id, new_id) in remap {
+            assert_eq!(data[index(new_id)], old_id);
+        }
+
+        let shrunk = make_storage(ShrinkStrategy::FillFromTail).shrink();
+        assert_eq!(shrunk.iter().map(|(_, &obj)| obj).collect::<Vec<_>>(), vec![9, 1, 7, 3, 4, 5]);
+
+        let mut storage = ShrinkableStorage::new();
+        storage.set_shrink_strategy(ShrinkStrategy::FillFromTail);
+        storage.extend(0..5);
+        storage.free_ids(vec![0, 1]);
+        assert_eq!(storage.shrink().iter().map(|(_, &obj)| obj).collect::<Vec<_>>(), vec![4, 3, 2]);
+        while !storage.compact_step(1).done {}
+        assert_eq!(storage.iter().map(|(_, &obj)| obj).collect::<Vec<_>>(), vec![4, 3, 2]);
+
+        // Every remap entry of a single-element step is one moved element.
+        let count_moves = |shrink_strategy| {
+            let mut storage = ShrinkableStorage::new();
+            storage.set_shrink_strategy(shrink_strategy);
+            storage.extend(0..6);
+            storage.free_ids(vec![1, 4]);
+
+            let mut moves = 0;
+            loop {
+                let progress = storage.compact_step(1);
+                moves += progress.remap.len();
+                if progress.done {
+                    break moves;
+                }
+            }
+        };
+
+        assert_eq!(count_moves(ShrinkStrategy::SwapRemove), 2);
+        assert_eq!(count_moves(ShrinkStrategy::FillFromTail), 1);
+    }
+
+    #[test]
+    fn test_shrinkable_storage_weak_id() {
+        let mut storage = ShrinkableStorage::new();
+        storage.set_track_generations(true);
+        storage.extend(vec!['a', 'b', 'c']);
+
+        let a = storage.weak_id(0).unwrap();
+        let c = WeakId::from(storage.gen_id(2).unwrap());
+        assert!(storage.is_alive(a));
+        assert!(storage.is_alive(c));
+
+        storage.free_id(0);
+        assert!(!storage.is_alive(a));
+        assert_eq!(storage.weak_id(0), None);
+
+        let storage = storage.shrink();
+        assert!(!storage.is_alive(a));
+        assert!(!storage.is_alive(c));
+        assert!(storage.is_alive(storage.weak_id(1).unwrap()));
+    }
+
+    #[test]
+    fn test_shrinkable_storage_reserve_slots() {
+        let mut storage = ShrinkableStorage::new();
+        storage.extend(vec![10, 20]);
+
+        let range = storage.reserve_slots(3);
+        assert_eq!(range, 2..5);
+        assert_eq!(storage.volume(), 5);
+        assert!(range.clone().all(|id| *storage.get(id) == 0));
+
+        let range = storage.reserve_slots_with(2, |id| index(id) * 100);
+        assert_eq!(range, 5..7);
+        assert_eq!(storage.volume(), 7);
+        assert_eq!(*storage.get(6), 600);
+
+        assert_eq!(storage.reserve_slots(0), 7..7);
+    }
+
+    #[test]
+    #[should_panic(expected = "id space is exhausted")]
+    fn test_shrinkable_storage_reserve_slots_past_id_space() {
+        let mut storage = ShrinkableStorage::<()>::new();
+        storage.extend(vec![(), ()]);
+        storage.reserve_slots_with(index(Id::MAX) - 1, |_| ());
+    }
+
+    #[test]
+    fn test_shrinkable_storage_shrink_keeping_spare() {
+        let mut storage = ShrinkableStorage::new();
+        storage.extend(0..100);
+        storage.free_ids(10..100);
+
+        let remap = storage.shrink_keeping_spare(20);
+        assert_eq!(remap.len(), 10);
+        assert_eq!(storage.volume(), 10);
+        assert!(storage.data.capacity() >= 30);
+        assert!(storage.data.capacity() < 100);
+
+        storage.free_id(0);
+        storage.shrink_keeping_spare(50);
+        assert_eq!(storage.volume(), 9);
+        assert!(storage.data.capacity() >= 59);
+    }
+
+    #[test]
+    fn test_shrinkable_storage_meta() {
+        for &shrink_strategy in [ShrinkStrategy::SwapRemove, ShrinkStrategy::FillFromTail].iter() {
+            let mut storage = ShrinkableStorage::<u32, String>::with_meta();
+            storage.set_shrink_strategy(shrink_strategy);
+            for i in 0..10 {
+                storage.insert_with_meta(i, format!("meta {}", i));
+            }
+            storage.extend(once(10));
+            *storage.meta_mut(0) = "first".to_string();
+
+            storage.free_ids(vec![1, 4, 5, 9]);
+
+            let check = |storage: &ShrinkableStorage<u32, String>| {
+                for (id, &obj) in storage.iter() {
+                    let expected = match obj {
+                        0 => "first".to_string(),
+                        10 => String::new(),
+                        obj => format!("meta {}", obj),
+                    };
+                    assert_eq!(storage.meta(id), &expected);
+                }
+            };
+
+            let new_storage = storage.shrink();
+            assert_eq!(new_storage.volume(), 7);
+            check(&new_storage);
+
+            let mut steps = storage.clone();
+            while !steps.compact_step(1).done {}
+            check(&steps);
+
+            storage.shrink_range(0..6);
+            check(&storage);
+            storage.shrink_keeping_spare(0);
+            assert_eq!(storage.volume(), 7);
+            check(&storage);
+        }
+    }
+
+    #[test]
+    fn test_shrinkable_storage_shrink_if() {
+        let mut storage = ShrinkableStorage::new();
+        assert!(storage.shrink_if(|fragmentation| fragmentation >= 0.0).is_some());
+
+        storage.extend(0..10);
+        storage.free_ids(vec![2, 7]);
+        assert_eq!(storage.fragmentation(), 0.2);
+
+        assert!(storage.shrink_if(|fragmentation| fragmentation > 0.3).is_none());
+        assert_eq!(storage.volume(), 10);
+
+        storage.free_id(4);
+        let remap = storage.shrink_if(|fragmentation| fragmentation > 0.25).unwrap();
+        assert_eq!(storage.volume(), 7);
+        assert_eq!(storage.fragmentation(), 0.0);
+        for (old_id, new_id) in remap {
+            assert_eq!(*storage.get(new_id), index(old_id));
+        }
+    }
+
+    #[test]
+    fn test_shrinkable_storage_reset() {
+        let mut storage = ShrinkableStorage::with_capacity(64);
+        storage.set_track_generations(true);
+        storage.extend(0..50);
+        storage.free_ids(vec![3, 10, 20]);
+        let gen_id = storage.gen_id(0).unwrap();
+        let capacity = storage.data.capacity();
+
+        storage.reset();
+        assert_eq!(storage.volume(), 0);
+        assert!(storage.is_empty());
+        assert!(!storage.is_id_free(&3));
+        assert_eq!(storage.data.capacity(), capacity);
+
+        assert_eq!(storage.insert(5), 0);
+        assert_eq!(storage.get_by_gen(gen_id), None);
+        assert_eq!(storage.data.capacity(), capacity);
+    }
+
+    #[test]
+    fn test_shrinkable_storage_live_ids() {
+        let mut storage = ShrinkableStorage::new();
+        assert_eq!(storage.live_ids().count(), 0);
+
+        storage.extend(0..10);
+        assert!(storage.live_ids().eq(0..10));
+
+        storage.free_ids(vec![0, 4, 5, 9]);
+        assert_eq!(storage.live_ids().collect::<Vec<_>>(), vec![1, 2, 3, 6, 7, 8]);
+
+        let naive: Vec<_> = storage.iter_ids().filter(|id| !storage.is_id_free(id)).collect();
+        assert_eq!(storage.live_ids().collect::<Vec<_>>(), naive);
+
+        storage.free_ids(storage.iter_ids().collect::<Vec<_>>());
+        assert_eq!(storage.live_ids().count(), 0);
+    }
+
+    #[test]
+    fn test_shrinkable_storage_meta_bounds() {
+        struct Meta;
+
+        let mut storage = ShrinkableStorage::<u32, Meta>::with_meta();
+        storage.insert_with_meta(1, Meta);
+        storage.insert_with_meta(2, Meta);
+
+        assert_eq!(storage.get_cloned(0), Some(1));
+        assert_eq!(storage.take(1), Some(2));
+        assert_eq!(storage.get_cloned(1), None);
+    }
+
+    #[test]
+    fn test_shrinkable_storage_shrink_range_keeps_free_ids_below() {
+        let mut storage = ShrinkableStorage::new();
+        storage.extend(0..10);
+        storage.free_ids((3..10).collect::<Vec<_>>());
+
+        let remap = storage.shrink_range(5..10);
+        assert!(remap.is_empty());
+        assert_eq!(storage.volume(), 5);
+        assert!(storage.is_id_free(&3));
+        assert!(storage.is_id_free(&4));
+        assert!(storage.live_ids().eq(0..3));
+    }
+}